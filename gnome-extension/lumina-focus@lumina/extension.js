@@ -0,0 +1,43 @@
+// Exposes the focused window on the session bus. GNOME Shell no longer
+// answers org.gnome.Shell.Eval, and Wayland gives other clients no way to
+// ask, so Lumina calls this instead.
+import Gio from 'gi://Gio';
+import {Extension} from 'resource:///org/gnome/shell/extensions/extension.js';
+
+const BUS_NAME = 'io.github.lumina.FocusedWindow';
+const OBJECT_PATH = '/io/github/lumina/FocusedWindow';
+const INTERFACE = `
+<node>
+  <interface name="io.github.lumina.FocusedWindow">
+    <method name="Get">
+      <arg type="s" direction="out" name="window"/>
+    </method>
+  </interface>
+</node>`;
+
+export default class LuminaFocusExtension extends Extension {
+    enable() {
+        this._object = Gio.DBusExportedObject.wrapJSObject(INTERFACE, this);
+        this._object.export(Gio.DBus.session, OBJECT_PATH);
+        this._nameId = Gio.bus_own_name(Gio.BusType.SESSION, BUS_NAME, Gio.BusNameOwnerFlags.NONE, null, null, null);
+    }
+
+    disable() {
+        Gio.bus_unown_name(this._nameId);
+        this._object.unexport();
+        this._object = null;
+    }
+
+    // JSON {app_id, title, fullscreen}, or an empty string when nothing has focus
+    Get() {
+        const window = global.display.focus_window;
+        if (!window)
+            return '';
+
+        return JSON.stringify({
+            app_id: window.get_gtk_application_id() || window.get_wm_class() || '',
+            title: window.get_title() || '',
+            fullscreen: window.is_fullscreen(),
+        });
+    }
+}
@@ -11,8 +11,19 @@ SERVER_PID=$!
 # 3. Give the server a moment to start up
 sleep 2
 
-# 4. URL where your index.html is served
-URL="index.html"
+# 4. The server serves the UI itself; the API token goes in the URL fragment,
+#    which the browser never sends to the server
+TOKEN_FILE="${XDG_CONFIG_HOME:-$HOME/.config}/lumina/api-token"
+ADDRESS="localhost:8080"
+IFS=',' read -ra LISTEN <<< "${LUMINA_LISTEN:-}"
+for entry in "${LISTEN[@]}"; do
+  entry="${entry// /}"
+  if [[ -n "$entry" && "$entry" != unix* ]]; then
+    ADDRESS="$entry"
+    break
+  fi
+done
+URL="http://$ADDRESS/#token=$(cat "$TOKEN_FILE")"
 
 # 5. Open the URL in the default browser
 if command -v xdg-open >/dev/null 2>&1; then
@@ -20,7 +31,7 @@ if command -v xdg-open >/dev/null 2>&1; then
 elif command -v open >/dev/null 2>&1; then
   open "$URL"
 else
-  echo "Please open your browser and visit: $URL"
+  echo "Please open your browser and visit: http://$ADDRESS/#token=<contents of $TOKEN_FILE>"
 fi
 
 # 6. When the script is interrupted (Ctrl+C), kill the Rust server
@@ -0,0 +1,626 @@
+use ashpd::desktop::camera::Camera;
+use ashpd::zbus;
+use pipewire::spa::param::video::VideoFormat;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::capture::{start_camera_capture, FrameData};
+
+// Sensor readings older than this are ignored and the screen heuristic takes over
+const READING_MAX_AGE: Duration = Duration::from_secs(10);
+const SENSOR_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_IIO_SYSFS_ROOT: &str = "/sys/bus/iio/devices";
+const DEFAULT_IIO_POLL_MS: u64 = 1000;
+const DEFAULT_CAMERA_INTERVAL_SECS: u64 = 60;
+// Frames to let auto-exposure settle before the one that gets measured
+const CAMERA_WARMUP_FRAMES: usize = 5;
+const CAMERA_FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+// Lux that a mid-grey (0.46) image at a 1/30 s exposure corresponds to,
+// scaled by that exposure. A rough figure for typical laptop webcams.
+const CAMERA_REFERENCE_EXPOSURE_SECS: f64 = 1.0 / 30.0;
+const CAMERA_CALIBRATION: f64 = 100.0 * CAMERA_REFERENCE_EXPOSURE_SECS / 0.46;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AmbientReading {
+    pub lux: f64,
+    pub level: f64,
+    pub source: &'static str,
+    #[serde(skip)]
+    pub taken_at: Instant,
+    #[serde(skip)]
+    pub max_age: Duration,
+}
+
+pub type SharedAmbientReading = Arc<Mutex<Option<AmbientReading>>>;
+
+pub fn fresh_reading(latest: &SharedAmbientReading) -> Option<AmbientReading> {
+    latest
+        .lock()
+        .unwrap()
+        .clone()
+        .filter(|reading| reading.taken_at.elapsed() <= reading.max_age)
+}
+
+// Maps illuminance onto the 0.0–1.0 scale of FrameAnalysis.ambient_light_level.
+// Perceived brightness is roughly logarithmic, so the mapping is log10(lux + 1)
+// over log10(10001): a dark room (~1 lux) is 0.03, a dim room (~10 lux) 0.26,
+// a living room (~100 lux) 0.50, an office (~1000 lux) 0.75 and daylight
+// (10000 lux and above) 1.0.
+pub fn lux_to_level(lux: f64) -> f64 {
+    ((lux.max(0.0) + 1.0).log10() / 10001.0_f64.log10()).clamp(0.0, 1.0)
+}
+
+// Reads net.hadess.SensorProxy from iio-sensor-proxy. The bus defaults to the
+// system bus; LUMINA_SENSOR_BUS_ADDRESS points it at a private bus instead so a
+// mock service can stand in for real hardware.
+pub struct SensorProxyLight {
+    proxy: zbus::Proxy<'static>,
+}
+
+impl SensorProxyLight {
+    // Returns None when the service runs but the machine has no light sensor
+    pub async fn connect() -> zbus::Result<Option<Self>> {
+        let address = std::env::var("LUMINA_SENSOR_BUS_ADDRESS").ok();
+        Self::connect_to(address.as_deref()).await
+    }
+
+    // None means the system bus
+    async fn connect_to(address: Option<&str>) -> zbus::Result<Option<Self>> {
+        let connection = match address {
+            Some(address) => zbus::connection::Builder::address(address)?.build().await?,
+            None => zbus::Connection::system().await?,
+        };
+
+        let proxy = zbus::Proxy::new(
+            &connection,
+            "net.hadess.SensorProxy",
+            "/net/hadess/SensorProxy",
+            "net.hadess.SensorProxy",
+        )
+        .await?;
+
+        if !proxy.get_property::<bool>("HasAmbientLight").await? {
+            return Ok(None);
+        }
+
+        proxy.call_method("ClaimLight", &()).await?;
+        Ok(Some(Self { proxy }))
+    }
+
+    pub async fn read(&self) -> zbus::Result<AmbientReading> {
+        let value: f64 = self.proxy.get_property("LightLevel").await?;
+        let unit: String = self.proxy.get_property("LightLevelUnit").await?;
+
+        // "vendor" units are an uncalibrated 0–100 scale, not lux
+        let level = if unit == "lux" { lux_to_level(value) } else { (value / 100.0).clamp(0.0, 1.0) };
+
+        Ok(AmbientReading {
+            lux: value,
+            level,
+            source: "iio-sensor-proxy",
+            taken_at: Instant::now(),
+            max_age: READING_MAX_AGE,
+        })
+    }
+}
+
+// Reads an IIO light sensor straight from sysfs for systems without
+// iio-sensor-proxy. LUMINA_IIO_SYSFS_ROOT replaces /sys/bus/iio/devices so a
+// fake tree can be used, and LUMINA_IIO_POLL_MS sets the poll interval.
+pub struct IioSysfsLight {
+    device_dir: PathBuf,
+}
+
+impl IioSysfsLight {
+    // First iio:device* exposing an illuminance channel
+    pub fn discover(root: &Path) -> io::Result<Option<Self>> {
+        let mut devices: Vec<PathBuf> = fs::read_dir(root)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("iio:device"))
+            .map(|entry| entry.path())
+            .collect();
+        devices.sort();
+
+        Ok(devices
+            .into_iter()
+            .find(|dir| dir.join("in_illuminance_input").exists() || dir.join("in_illuminance_raw").exists())
+            .map(|device_dir| Self { device_dir }))
+    }
+
+    pub fn read(&self) -> io::Result<AmbientReading> {
+        // The processed channel is already in lux; raw needs (raw + offset) * scale
+        let lux = match self.read_value("in_illuminance_input")? {
+            Some(lux) => lux,
+            None => {
+                let raw = self
+                    .read_value("in_illuminance_raw")?
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no illuminance channel"))?;
+                let offset = self.read_value("in_illuminance_offset")?.unwrap_or(0.0);
+                let scale = self.read_value("in_illuminance_scale")?.unwrap_or(1.0);
+                (raw + offset) * scale
+            },
+        };
+
+        Ok(AmbientReading {
+            lux,
+            level: lux_to_level(lux),
+            source: "iio-sysfs",
+            taken_at: Instant::now(),
+            max_age: READING_MAX_AGE,
+        })
+    }
+
+    fn read_value(&self, attribute: &str) -> io::Result<Option<f64>> {
+        match fs::read_to_string(self.device_dir.join(attribute)) {
+            Ok(text) => text
+                .trim()
+                .parse::<f64>()
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", attribute, e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Estimates room light from an occasional webcam frame. Opt in with
+// LUMINA_CAMERA_AMBIENT=1 and name the V4L2 device in LUMINA_CAMERA_DEVICE:
+// its exposure time, read back with v4l2-ctl, is what turns the image into a
+// light level. LUMINA_CAMERA_NODE picks a PipeWire node directly instead of
+// asking the Camera portal; such a node may have no V4L2 device behind it
+// (e.g. videotestsrc), and its exposure is then taken to be the reference
+// one. LUMINA_CAMERA_INTERVAL_SECS sets how often a frame is taken. Frames
+// never leave this function.
+pub struct CameraLight {
+    node_id: Option<u32>,
+    device: Option<String>,
+    // Access is asked for once; each reading then opens a fresh remote
+    portal: Option<Camera<'static>>,
+}
+
+impl CameraLight {
+    pub fn from_env() -> Option<Self> {
+        if std::env::var("LUMINA_CAMERA_AMBIENT").ok().as_deref() != Some("1") {
+            return None;
+        }
+
+        let node_id = std::env::var("LUMINA_CAMERA_NODE").ok().and_then(|id| id.parse().ok());
+        let device = std::env::var("LUMINA_CAMERA_DEVICE").ok().filter(|device| !device.is_empty());
+        Self::new(node_id, device)
+            .map_err(|e| eprintln!("{}; not using the webcam", e))
+            .ok()
+    }
+
+    fn new(node_id: Option<u32>, device: Option<String>) -> Result<Self, String> {
+        match (node_id, &device) {
+            // Auto-exposure drags every scene towards mid-grey, so without the
+            // exposure time a real webcam says next to nothing about the room
+            (None, None) => return Err("LUMINA_CAMERA_AMBIENT needs LUMINA_CAMERA_DEVICE (e.g. /dev/video0)".to_string()),
+            (Some(node_id), None) => {
+                println!("No LUMINA_CAMERA_DEVICE for node {}; assuming a fixed exposure", node_id);
+            },
+            _ => {},
+        }
+        Ok(Self { node_id, device, portal: None })
+    }
+
+    async fn pipewire_remote(&mut self) -> Result<Option<OwnedFd>, Box<dyn std::error::Error + Send + Sync>> {
+        if self.node_id.is_some() {
+            return Ok(None);
+        }
+
+        let portal = match self.portal.take() {
+            Some(portal) => portal,
+            None => {
+                let portal = Camera::new().await?;
+                portal.request_access().await?.response()?;
+                if !portal.is_present().await? {
+                    return Err("no camera available".into());
+                }
+                portal
+            },
+        };
+        let remote = portal.open_pipe_wire_remote().await?;
+        self.portal = Some(portal);
+        Ok(Some(remote))
+    }
+
+    pub async fn read(&mut self) -> Result<AmbientReading, Box<dyn std::error::Error + Send + Sync>> {
+        let remote = self.pipewire_remote().await?;
+        let mut receiver = start_camera_capture(remote, self.node_id);
+
+        let mut luma = None;
+        for _ in 0..CAMERA_WARMUP_FRAMES {
+            match tokio::time::timeout(CAMERA_FRAME_TIMEOUT, receiver.changed()).await {
+                Ok(Ok(())) => {
+                    if let Some(frame) = receiver.borrow_and_update().as_deref() {
+                        luma = mean_luma(frame).or(luma);
+                    }
+                },
+                Ok(Err(_)) => break,
+                Err(_) => return Err("timed out waiting for a camera frame".into()),
+            }
+        }
+
+        // Read while the stream still runs, before the driver can reset it
+        let exposure = match self.device.clone() {
+            Some(device) => {
+                let exposure = tokio::task::spawn_blocking(move || read_exposure_secs(&device).ok_or(device)).await?;
+                Some(exposure.map_err(|device| format!("could not read the exposure time of {}", device))?)
+            },
+            None => None,
+        };
+
+        // Dropping the receiver stops the stream and releases the camera
+        drop(receiver);
+
+        let luma = luma.ok_or("camera produced no usable frame")?;
+        let lux = camera_lux(luma, exposure);
+        Ok(AmbientReading {
+            lux,
+            level: lux_to_level(lux),
+            source: "camera",
+            taken_at: Instant::now(),
+            max_age: READING_MAX_AGE,
+        })
+    }
+}
+
+// Dividing by the exposure time recovers the scene level
+fn camera_lux(luma: f64, exposure_secs: Option<f64>) -> f64 {
+    CAMERA_CALIBRATION * luma / exposure_secs.unwrap_or(CAMERA_REFERENCE_EXPOSURE_SECS)
+}
+
+// Mean luma in 0.0–1.0 for the formats the capture stream negotiates
+fn mean_luma(frame: &FrameData) -> Option<f64> {
+    let pixels = (frame.width * frame.height) as usize;
+    if pixels == 0 {
+        return None;
+    }
+
+    let sum: u64 = match frame.format {
+        // Y0 U Y1 V
+        VideoFormat::YUY2 if frame.data.len() >= pixels * 2 => {
+            frame.data.iter().step_by(2).take(pixels).map(|&y| y as u64).sum()
+        },
+        // Only the leading Y plane is copied out of the buffer
+        VideoFormat::I420 if frame.data.len() >= pixels => {
+            frame.data[..pixels].iter().map(|&y| y as u64).sum()
+        },
+        VideoFormat::RGB if frame.data.len() >= pixels * 3 => {
+            frame.data.chunks_exact(3).take(pixels).map(|p| rgb_luma(p[0], p[1], p[2])).sum()
+        },
+        VideoFormat::RGBA | VideoFormat::RGBx if frame.data.len() >= pixels * 4 => {
+            frame.data.chunks_exact(4).take(pixels).map(|p| rgb_luma(p[0], p[1], p[2])).sum()
+        },
+        VideoFormat::BGRx if frame.data.len() >= pixels * 4 => {
+            frame.data.chunks_exact(4).take(pixels).map(|p| rgb_luma(p[2], p[1], p[0])).sum()
+        },
+        _ => return None,
+    };
+
+    Some(sum as f64 / pixels as f64 / 255.0)
+}
+
+fn rgb_luma(r: u8, g: u8, b: u8) -> u64 {
+    (299 * r as u64 + 587 * g as u64 + 114 * b as u64) / 1000
+}
+
+// Exposure time in seconds; V4L2 reports it in units of 100 µs
+fn read_exposure_secs(device: &str) -> Option<f64> {
+    let output = Command::new("v4l2-ctl")
+        .args(["-d", device, "--get-ctrl=exposure_time_absolute", "--get-ctrl=exposure_absolute"])
+        .output()
+        .ok()?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find_map(|(_, value)| value.trim().parse::<f64>().ok())
+        .filter(|&units| units > 0.0)
+        .map(|units| units / 10_000.0)
+}
+
+// Keeps `latest` updated from the best available light source: iio-sensor-proxy,
+// then raw sysfs, then (if enabled) the webcam. Returns quietly when none is
+// present so the frame heuristic stays in charge.
+pub async fn run_ambient_sensors(latest: SharedAmbientReading) {
+    match SensorProxyLight::connect().await {
+        Ok(Some(sensor)) => {
+            println!("Reading ambient light from iio-sensor-proxy");
+            loop {
+                match sensor.read().await {
+                    Ok(reading) => *latest.lock().unwrap() = Some(reading),
+                    Err(e) => eprintln!("Failed to read ambient light level: {}", e),
+                }
+                tokio::time::sleep(SENSOR_POLL_INTERVAL).await;
+            }
+        },
+        Ok(None) => println!("No ambient light sensor reported by iio-sensor-proxy"),
+        Err(e) => println!("iio-sensor-proxy unavailable ({})", e),
+    }
+
+    let root = std::env::var_os("LUMINA_IIO_SYSFS_ROOT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_IIO_SYSFS_ROOT));
+    let poll_interval = std::env::var("LUMINA_IIO_POLL_MS")
+        .ok()
+        .and_then(|ms| ms.parse::<u64>().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(Duration::from_millis(DEFAULT_IIO_POLL_MS));
+
+    if let Ok(Some(sensor)) = IioSysfsLight::discover(&root) {
+        println!("Reading ambient light from {}", sensor.device_dir.display());
+        loop {
+            match sensor.read() {
+                Ok(reading) => *latest.lock().unwrap() = Some(reading),
+                Err(e) => eprintln!("Failed to read ambient light level: {}", e),
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+    println!("No IIO light sensor under {}", root.display());
+
+    let Some(mut camera) = CameraLight::from_env() else {
+        println!("Estimating ambient light from the screen");
+        return;
+    };
+
+    let interval = std::env::var("LUMINA_CAMERA_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(DEFAULT_CAMERA_INTERVAL_SECS));
+
+    println!("Estimating ambient light from the webcam every {}s", interval.as_secs());
+    loop {
+        match camera.read().await {
+            // Camera readings are sparse, so keep each one until the next is due
+            Ok(mut reading) => {
+                reading.max_age = interval + CAMERA_FRAME_TIMEOUT;
+                *latest.lock().unwrap() = Some(reading);
+            },
+            Err(e) => eprintln!("Failed to estimate ambient light from the webcam: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Stdio};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn lux_to_level_spans_dark_to_daylight() {
+        assert_eq!(lux_to_level(0.0), 0.0);
+        assert!((lux_to_level(10_000.0) - 1.0).abs() < 1e-12);
+        assert!((lux_to_level(100.0) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn lux_to_level_clamps() {
+        assert_eq!(lux_to_level(-5.0), 0.0);
+        assert_eq!(lux_to_level(50_000.0), 1.0);
+    }
+
+    fn fake_device(root: &Path, name: &str, attributes: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (attribute, value) in attributes {
+            fs::write(dir.join(attribute), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    #[test]
+    fn iio_sysfs_reads_processed_lux() {
+        let root = crate::storage::test_dir("iio-input");
+        // An accelerometer comes first and has to be skipped
+        fake_device(&root, "iio:device0", &[("in_accel_x_raw", "12")]);
+        fake_device(&root, "iio:device1", &[("in_illuminance_input", "320.5")]);
+
+        let sensor = IioSysfsLight::discover(&root).unwrap().unwrap();
+        assert_eq!(sensor.device_dir, root.join("iio:device1"));
+        let reading = sensor.read().unwrap();
+        assert_eq!(reading.lux, 320.5);
+        assert_eq!(reading.level, lux_to_level(320.5));
+        assert_eq!(reading.source, "iio-sysfs");
+    }
+
+    #[test]
+    fn iio_sysfs_scales_raw_counts() {
+        let root = crate::storage::test_dir("iio-raw");
+        fake_device(&root, "iio:device0", &[
+            ("in_illuminance_raw", "1000"),
+            ("in_illuminance_offset", "-200"),
+            ("in_illuminance_scale", "0.25"),
+        ]);
+
+        let reading = IioSysfsLight::discover(&root).unwrap().unwrap().read().unwrap();
+        assert_eq!(reading.lux, 200.0);
+    }
+
+    #[test]
+    fn iio_sysfs_raw_defaults_and_errors() {
+        let root = crate::storage::test_dir("iio-defaults");
+        fake_device(&root, "iio:device0", &[("in_illuminance_raw", "75")]);
+        let sensor = IioSysfsLight::discover(&root).unwrap().unwrap();
+        assert_eq!(sensor.read().unwrap().lux, 75.0);
+
+        fs::write(root.join("iio:device0/in_illuminance_raw"), "garbage\n").unwrap();
+        assert_eq!(sensor.read().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn iio_sysfs_without_light_sensor() {
+        let root = crate::storage::test_dir("iio-none");
+        fake_device(&root, "iio:device0", &[("in_accel_x_raw", "12")]);
+        assert!(IioSysfsLight::discover(&root).unwrap().is_none());
+        assert!(IioSysfsLight::discover(&root.join("missing")).is_err());
+    }
+
+    // Stands in for iio-sensor-proxy
+    struct FakeSensorProxy {
+        has_light: bool,
+        level: f64,
+        unit: &'static str,
+        claimed: Arc<AtomicBool>,
+    }
+
+    #[zbus::interface(name = "net.hadess.SensorProxy")]
+    impl FakeSensorProxy {
+        #[zbus(property)]
+        fn has_ambient_light(&self) -> bool {
+            self.has_light
+        }
+
+        #[zbus(property)]
+        fn light_level(&self) -> f64 {
+            self.level
+        }
+
+        #[zbus(property)]
+        fn light_level_unit(&self) -> String {
+            self.unit.to_string()
+        }
+
+        fn claim_light(&self) {
+            self.claimed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // A dbus-daemon of the test's own, stopped on drop
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl PrivateBus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("dbus-daemon is needed for the D-Bus tests");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address).unwrap();
+            Self { daemon, address: address.trim().to_string() }
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    async fn serve(bus: &PrivateBus, sensor: FakeSensorProxy) -> zbus::Connection {
+        zbus::connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name("net.hadess.SensorProxy")
+            .unwrap()
+            .serve_at("/net/hadess/SensorProxy", sensor)
+            .unwrap()
+            .build()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sensor_proxy_reads_lux() {
+        let bus = PrivateBus::start();
+        let claimed = Arc::new(AtomicBool::new(false));
+        let _service = serve(&bus, FakeSensorProxy { has_light: true, level: 100.0, unit: "lux", claimed: claimed.clone() }).await;
+
+        let sensor = SensorProxyLight::connect_to(Some(&bus.address)).await.unwrap().unwrap();
+        assert!(claimed.load(Ordering::SeqCst));
+
+        let reading = sensor.read().await.unwrap();
+        assert_eq!(reading.lux, 100.0);
+        assert_eq!(reading.level, lux_to_level(100.0));
+        assert_eq!(reading.source, "iio-sensor-proxy");
+    }
+
+    #[tokio::test]
+    async fn sensor_proxy_scales_vendor_units() {
+        let bus = PrivateBus::start();
+        let claimed = Arc::new(AtomicBool::new(false));
+        let _service = serve(&bus, FakeSensorProxy { has_light: true, level: 40.0, unit: "vendor", claimed }).await;
+
+        let sensor = SensorProxyLight::connect_to(Some(&bus.address)).await.unwrap().unwrap();
+        assert!((sensor.read().await.unwrap().level - 0.4).abs() < 1e-12);
+    }
+
+    #[tokio::test]
+    async fn sensor_proxy_without_light_sensor() {
+        let bus = PrivateBus::start();
+        let claimed = Arc::new(AtomicBool::new(false));
+        let _service = serve(&bus, FakeSensorProxy { has_light: false, level: 0.0, unit: "lux", claimed: claimed.clone() }).await;
+
+        assert!(SensorProxyLight::connect_to(Some(&bus.address)).await.unwrap().is_none());
+        assert!(!claimed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn camera_needs_a_device_unless_given_a_node() {
+        assert!(CameraLight::new(None, None).is_err());
+        assert!(CameraLight::new(None, Some("/dev/video0".to_string())).is_ok());
+
+        let camera = CameraLight::new(Some(42), None).unwrap();
+        assert_eq!((camera.node_id, camera.device), (Some(42), None));
+    }
+
+    #[test]
+    fn camera_lux_scales_with_exposure() {
+        // Mid-grey at the reference exposure is the calibration point
+        assert!((camera_lux(0.46, Some(1.0 / 30.0)) - 100.0).abs() < 1e-9);
+        assert_eq!(camera_lux(0.46, None), camera_lux(0.46, Some(1.0 / 30.0)));
+        // The same image from a longer exposure means a darker room
+        assert!((camera_lux(0.46, Some(1.0 / 3.0)) - 10.0).abs() < 1e-9);
+        assert_eq!(camera_lux(0.0, None), 0.0);
+    }
+
+    fn frame(format: VideoFormat, data: Vec<u8>) -> FrameData {
+        FrameData { data, width: 2, height: 2, format, timestamp: Instant::now(), sequence: 1 }
+    }
+
+    #[test]
+    fn mean_luma_reads_each_format() {
+        assert_eq!(mean_luma(&frame(VideoFormat::YUY2, vec![255, 0, 255, 0, 0, 0, 0, 0])), Some(0.5));
+        assert_eq!(mean_luma(&frame(VideoFormat::I420, vec![255, 255, 255, 255, 0, 0])), Some(1.0));
+        assert_eq!(mean_luma(&frame(VideoFormat::RGB, [255, 255, 255].repeat(4))), Some(1.0));
+        // Red weighs 0.299 whichever end of the pixel it sits at
+        let red = rgb_luma(255, 0, 0) as f64 / 255.0;
+        assert_eq!(mean_luma(&frame(VideoFormat::RGBx, [255, 0, 0, 255].repeat(4))), Some(red));
+        assert_eq!(mean_luma(&frame(VideoFormat::BGRx, [0, 0, 255, 255].repeat(4))), Some(red));
+
+        // Short buffers and unknown formats are no reading
+        assert_eq!(mean_luma(&frame(VideoFormat::RGB, vec![255; 11])), None);
+        assert_eq!(mean_luma(&frame(VideoFormat::BGRA, vec![255; 16])), None);
+    }
+
+    // Needs a PipeWire daemon with a video source node and no V4L2 device, e.g.
+    //   gst-launch-1.0 videotestsrc pattern=white ! pipewiresink &
+    //   LUMINA_TEST_CAMERA_NODE=<node id from pw-cli ls Node> cargo test -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn camera_reads_a_pipewire_node() {
+        let node_id = std::env::var("LUMINA_TEST_CAMERA_NODE").unwrap().parse().unwrap();
+        let mut camera = CameraLight::new(Some(node_id), None).unwrap();
+
+        let reading = camera.read().await.unwrap();
+        assert_eq!(reading.source, "camera");
+        assert!(reading.lux > 0.0);
+        assert_eq!(reading.level, lux_to_level(reading.lux));
+    }
+}
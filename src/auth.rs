@@ -0,0 +1,322 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, ResponseError};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::clock::unix_millis;
+use crate::error::ApiError;
+use crate::AppState;
+
+// Browsers can't put headers on WebSocket or EventSource requests, so those
+// trade the token for a ticket first and pass it in the query string. A
+// ticket opens only the path it was first used on, and only briefly, so it's
+// harmless in access logs. EventSource reconnects with the same URL, so
+// repeat connections to that path are let through until the ticket expires.
+pub const TICKET_TTL_MS: u64 = 30_000;
+
+// Reachable without a token: nothing here reads or changes any state
+const PUBLIC_PATHS: [&str; 5] = ["/", "/health", "/api/v1/health", "/api/v1/openapi.json", "/api/v1/docs"];
+const TICKET_PATHS: [&str; 4] = ["/ws", "/events", "/api/v1/ws", "/api/v1/events"];
+
+struct Ticket {
+    expires_at: u64,
+    // Set on first use
+    path: Option<String>,
+}
+
+pub struct Auth {
+    token: String,
+    tickets: Mutex<HashMap<String, Ticket>>,
+}
+
+fn random_hex(bytes: usize) -> io::Result<String> {
+    let mut buffer = vec![0u8; bytes];
+    fs::File::open("/dev/urandom")?.read_exact(&mut buffer)?;
+    Ok(buffer.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Compares in time independent of where the first difference is
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Auth {
+    // Reads the token from `path`, creating it readable by the owner only on
+    // first start
+    pub fn load_or_create(path: &Path) -> io::Result<Self> {
+        let token = match fs::read_to_string(path) {
+            Ok(token) if !token.trim().is_empty() => {
+                let permissions = fs::metadata(path)?.permissions();
+                if permissions.mode() & 0o077 != 0 {
+                    eprintln!("Restricting {} to its owner", path.display());
+                    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+                }
+                token.trim().to_string()
+            },
+            Ok(_) => Self::create(path, true)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Self::create(path, false)?,
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self { token, tickets: Mutex::new(HashMap::new()) })
+    }
+
+    fn create(path: &Path, replace: bool) -> io::Result<String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if replace {
+            fs::remove_file(path)?;
+        }
+
+        let token = random_hex(32)?;
+        // create_new, so the file is never briefly readable with wider permissions
+        let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+        writeln!(file, "{}", token)?;
+        Ok(token)
+    }
+
+    fn is_valid_token(&self, candidate: &str) -> bool {
+        constant_time_eq(candidate.as_bytes(), self.token.as_bytes())
+    }
+
+    pub fn issue_ticket(&self) -> io::Result<String> {
+        let ticket = random_hex(16)?;
+        let now = unix_millis();
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.retain(|_, ticket| ticket.expires_at > now);
+        tickets.insert(ticket.clone(), Ticket { expires_at: now + TICKET_TTL_MS, path: None });
+        Ok(ticket)
+    }
+
+    fn redeem_ticket(&self, ticket: &str, path: &str) -> bool {
+        let mut tickets = self.tickets.lock().unwrap();
+        let Some(entry) = tickets.get_mut(ticket) else {
+            return false;
+        };
+        if entry.expires_at <= unix_millis() {
+            tickets.remove(ticket);
+            return false;
+        }
+        entry.path.get_or_insert_with(|| path.to_string()) == path
+    }
+
+    fn authorize(&self, req: &ServiceRequest) -> bool {
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if let Some(token) = bearer {
+            return self.is_valid_token(token.trim());
+        }
+
+        if TICKET_PATHS.contains(&req.path()) {
+            let ticket = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+                .ok()
+                .and_then(|query| query.get("ticket").cloned());
+            return ticket.is_some_and(|ticket| self.redeem_ticket(&ticket, req.path()));
+        }
+        false
+    }
+}
+
+// Wrapped inside the CORS middleware, so preflight requests never get here
+// and rejections still carry CORS headers. Connections over the Unix socket
+// have no peer address; its file permissions already decide who gets in.
+pub async fn require_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let authorized = req.peer_addr().is_none()
+        || PUBLIC_PATHS.contains(&req.path())
+        || req.app_data::<web::Data<AppState>>().is_some_and(|data| data.auth.authorize(&req));
+
+    if !authorized {
+        let response = ApiError::Unauthorized.error_response();
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::App;
+    use std::sync::Arc;
+
+    use crate::window_tracker::MockWindowTracker;
+
+    fn test_auth() -> Auth {
+        Auth { token: "secret-token".to_string(), tickets: Mutex::new(HashMap::new()) }
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_values() {
+        assert!(constant_time_eq(b"abc123", b"abc123"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"abc123", b"abc124"));
+        assert!(!constant_time_eq(b"xbc123", b"abc123"));
+        assert!(!constant_time_eq(b"abc", b"abc123"));
+        assert!(!constant_time_eq(b"abc123", b""));
+    }
+
+    #[test]
+    fn tickets_open_only_their_first_path() {
+        let auth = test_auth();
+        let ticket = auth.issue_ticket().unwrap();
+        assert_eq!(ticket.len(), 32);
+        assert!(auth.redeem_ticket(&ticket, "/api/v1/events"));
+        assert!(auth.redeem_ticket(&ticket, "/api/v1/events"));
+        assert!(!auth.redeem_ticket(&ticket, "/api/v1/ws"));
+        assert!(!auth.redeem_ticket("not-a-ticket", "/api/v1/events"));
+    }
+
+    #[test]
+    fn expired_tickets_are_refused_and_pruned() {
+        let auth = test_auth();
+        let stale = || Ticket { expires_at: unix_millis() - 1, path: None };
+        auth.tickets.lock().unwrap().insert("stale".to_string(), stale());
+        assert!(!auth.redeem_ticket("stale", "/events"));
+        assert!(!auth.tickets.lock().unwrap().contains_key("stale"));
+
+        auth.tickets.lock().unwrap().insert("stale".to_string(), stale());
+        auth.issue_ticket().unwrap();
+        assert!(!auth.tickets.lock().unwrap().contains_key("stale"));
+
+        // Reconnects stop once the ticket expires
+        let ticket = auth.issue_ticket().unwrap();
+        assert!(auth.redeem_ticket(&ticket, "/events"));
+        auth.tickets.lock().unwrap().get_mut(&ticket).unwrap().expires_at = unix_millis() - 1;
+        assert!(!auth.redeem_ticket(&ticket, "/events"));
+    }
+
+    #[test]
+    fn token_file_is_created_private_and_reused() {
+        let path = crate::storage::test_dir("auth-token").join("api-token");
+        let created = Auth::load_or_create(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let loaded = Auth::load_or_create(&path).unwrap();
+        assert_eq!(loaded.token, created.token);
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    fn state(name: &str) -> web::Data<AppState> {
+        web::Data::new(crate::tests::test_state(name, Arc::new(MockWindowTracker::default())))
+    }
+
+    // The real routes behind the token check
+    macro_rules! service {
+        ($state:expr) => {
+            init_service(
+                App::new()
+                    .wrap(actix_web::middleware::from_fn(require_token))
+                    .app_data($state.clone())
+                    .configure(crate::routes),
+            )
+            .await
+        };
+    }
+
+    // Requests over TCP carry a peer address
+    fn request(method: Method, uri: &str) -> TestRequest {
+        TestRequest::default().method(method).uri(uri).peer_addr("127.0.0.1:40000".parse().unwrap())
+    }
+
+    #[actix_web::test]
+    async fn requests_need_the_right_bearer_token() {
+        let state = state("auth-bearer");
+        let app = service!(state);
+        let token = state.auth.token.clone();
+
+        for uri in ["/api/v1/status", "/config", "/api/v1/profiles"] {
+            let response = call_service(&app, request(Method::GET, uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+            assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+
+            let wrong = request(Method::GET, uri).insert_header((header::AUTHORIZATION, "Bearer wrong"));
+            assert_eq!(call_service(&app, wrong.to_request()).await.status(), StatusCode::UNAUTHORIZED);
+
+            // The token itself is no good without the scheme
+            let bare = request(Method::GET, uri).insert_header((header::AUTHORIZATION, token.as_str()));
+            assert_eq!(call_service(&app, bare.to_request()).await.status(), StatusCode::UNAUTHORIZED);
+
+            let right = request(Method::GET, uri).insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+            assert_eq!(call_service(&app, right.to_request()).await.status(), StatusCode::OK, "{}", uri);
+        }
+
+        // Changes are refused just the same
+        let response = call_service(&app, request(Method::POST, "/api/v1/start").to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!state.status.lock().unwrap().running);
+    }
+
+    #[actix_web::test]
+    async fn public_paths_reveal_no_state() {
+        let state = state("auth-public");
+        let app = service!(state);
+        // Values that appear nowhere in the static pages or the spec
+        state.config.lock().unwrap().temperature = 4321;
+        state.status.lock().unwrap().frames_processed = 987_654_321;
+
+        for path in PUBLIC_PATHS {
+            let response = call_service(&app, request(Method::GET, path).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+            let body = read_body(response).await;
+            let body = String::from_utf8_lossy(&body);
+            assert!(!body.contains(&state.auth.token), "{}", path);
+            assert!(!body.contains("4321"), "{}", path);
+            assert!(!body.contains("987654321"), "{}", path);
+
+            // Only reading is public; anything else finds no handler
+            for method in [Method::POST, Method::PUT, Method::DELETE] {
+                let response = call_service(&app, request(method.clone(), path).to_request()).await;
+                assert!(!response.status().is_success(), "{} {}", method, path);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn tickets_only_open_the_event_streams() {
+        let state = state("auth-tickets");
+        let app = service!(state);
+        let ticket = state.auth.issue_ticket().unwrap();
+
+        let uri = format!("/api/v1/status?ticket={}", ticket);
+        let response = call_service(&app, request(Method::GET, &uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Refused without being used up
+        let uri = format!("/api/v1/events?ticket={}", ticket);
+        let response = call_service(&app, request(Method::GET, &uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // EventSource reconnects with the same URL and the last id it saw
+        let reconnect = request(Method::GET, &uri).insert_header(("Last-Event-ID", "1"));
+        assert_eq!(call_service(&app, reconnect.to_request()).await.status(), StatusCode::OK);
+
+        // But the ticket opens nothing else
+        let uri = format!("/events?ticket={}", ticket);
+        let response = call_service(&app, request(Method::GET, &uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn unix_socket_requests_skip_the_token() {
+        let _state = state("auth-unix");
+        let app = service!(_state);
+        let request = TestRequest::get().uri("/api/v1/status").to_request();
+        assert_eq!(call_service(&app, request).await.status(), StatusCode::OK);
+    }
+}
@@ -1,31 +1,266 @@
+use std::future::Future;
 use std::os::fd::{IntoRawFd, OwnedFd};
+use std::pin::Pin;
 use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
+use tokio::sync::watch;
 use std::thread;
 
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
 use ashpd::desktop::{
     screencast::{CursorMode, Screencast, SourceType, Stream as ScreencastStream},
     PersistMode,
 };
 use pipewire as pw;
 use pw::{properties::properties, spa};
+use pw::spa::param::video::VideoFormat;
+
+use crate::error::FieldError;
+use crate::recording::ReplaySource;
+use crate::screencopy::ScreencopySource;
+use crate::synthetic::SyntheticSource;
+use crate::x11_shm::X11ShmSource;
+
+// Width of the window the delivered framerate is averaged over
+const DELIVERY_WINDOW: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Clone)]
 pub struct FrameData {
     pub data: Vec<u8>,
     pub width: u32,
     pub height: u32,
-    pub format: pw::spa::param::video::VideoFormat,
+    pub format: VideoFormat,
     pub timestamp: Instant,
+    pub sequence: u64,
+}
+
+// Holds only the newest frame; a consumer that falls behind skips the stale
+// ones instead of queueing them, and gaps in `sequence` count the skipped frames
+pub type FrameReceiver = watch::Receiver<Option<Arc<FrameData>>>;
+type FrameSender = watch::Sender<Option<Arc<FrameData>>>;
+
+// What to ask the compositor for. The analysis only needs a coarse picture,
+// so a small downscaled stream is preferred; sources that cannot scale may
+// answer with any size inside the min/max bounds.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct CaptureSettings {
+    pub frame_interval_ms: u64, // Minimum time between frames handed to the analysis
+    pub preferred_width: u32,
+    pub preferred_height: u32,
+    pub min_width: u32,
+    pub min_height: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_framerate: u32,
+}
+
+// What was actually negotiated, plus how fast frames really arrive
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StreamInfo {
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub framerate: f64, // Nominal rate; for variable-rate sources their maximum
+    pub variable_framerate: bool,
+    pub delivered_fps: f64,
+    // Sources that capture a single monitor name the one they picked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+pub type SharedStreamInfo = Arc<Mutex<Option<StreamInfo>>>;
+
+pub type CaptureError = Box<dyn std::error::Error + Send + Sync>;
+pub type StartFuture<'a> = Pin<Box<dyn Future<Output = Result<FrameReceiver, CaptureError>> + Send + 'a>>;
+
+// Anything frames can come from. A stream runs until stop() or until every
+// receiver handed out by start() has been dropped.
+pub trait FrameSource: Send + Sync {
+    fn name(&self) -> &'static str;
+    // May wait on the user, e.g. for the portal's screen picker
+    fn start<'a>(&'a self, settings: &'a CaptureSettings) -> StartFuture<'a>;
+    fn stop(&self);
+    // Format, size and rate of the running stream, once known
+    fn metadata(&self) -> Option<StreamInfo>;
+}
+
+// Picks the frame source for this run: `--synthetic` on the command line,
+// then a recording named by LUMINA_REPLAY, then the screen. LUMINA_CAPTURE
+// (portal, x11-shm or wlr-screencopy) forces a screen source; otherwise
+// X11-only sessions grab the root window, wlroots compositors are copied
+// from directly and everything else goes through the portal.
+pub fn select_frame_source(args: impl IntoIterator<Item = String>) -> Result<Box<dyn FrameSource>, String> {
+    if let Some(synthetic) = SyntheticSource::from_args(args)? {
+        return Ok(Box::new(synthetic));
+    }
+
+    if let Some(replay) = ReplaySource::from_env() {
+        return Ok(Box::new(replay));
+    }
+
+    match std::env::var("LUMINA_CAPTURE").ok().as_deref() {
+        Some("portal") => return Ok(Box::new(PortalSource::default())),
+        Some("x11-shm") => return Ok(Box::new(X11ShmSource::default())),
+        Some("wlr-screencopy") => return Ok(Box::new(ScreencopySource::default())),
+        Some(other) => return Err(format!("unknown LUMINA_CAPTURE source '{}'", other)),
+        None => {},
+    }
+
+    if std::env::var_os("WAYLAND_DISPLAY").is_none() && std::env::var_os("DISPLAY").is_some() {
+        return Ok(Box::new(X11ShmSource::default()));
+    }
+
+    if let Some(screencopy) = ScreencopySource::detect() {
+        return Ok(Box::new(screencopy));
+    }
+
+    Ok(Box::new(PortalSource::default()))
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            frame_interval_ms: 500,
+            preferred_width: 640,
+            preferred_height: 360,
+            min_width: 160,
+            min_height: 90,
+            max_width: 4096,
+            max_height: 4096,
+            max_framerate: 60,
+        }
+    }
+}
+
+impl CaptureSettings {
+    // Cameras deliver every frame; the ambient estimate wants them all
+    pub fn camera() -> Self {
+        Self {
+            frame_interval_ms: 0,
+            preferred_width: 320,
+            preferred_height: 240,
+            max_framerate: 30,
+            ..Self::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), FieldError> {
+        if self.min_width == 0 || self.min_height == 0 {
+            return Err(FieldError::new("min_width", "min_width and min_height must be at least 1"));
+        }
+        if self.max_width > 8192 || self.max_height > 8192 {
+            return Err(FieldError::new("max_width", "max_width and max_height must not exceed 8192"));
+        }
+        if !(self.min_width..=self.max_width).contains(&self.preferred_width)
+            || !(self.min_height..=self.max_height).contains(&self.preferred_height)
+        {
+            return Err(FieldError::new("preferred_width", "preferred size must lie within the min/max bounds"));
+        }
+        if !(1..=240).contains(&self.max_framerate) {
+            return Err(FieldError::new("max_framerate", "max_framerate must be between 1 and 240"));
+        }
+        if self.frame_interval_ms > 60_000 {
+            return Err(FieldError::new("frame_interval_ms", "frame_interval_ms must not exceed 60000"));
+        }
+        Ok(())
+    }
+
+    fn frame_interval(&self) -> Duration {
+        Duration::from_millis(self.frame_interval_ms)
+    }
+
+    // For sources that produce frames on their own timer. An interval of 0
+    // means every frame, which for them is as fast as max_framerate allows.
+    pub fn poll_interval(&self) -> Duration {
+        self.frame_interval().max(Duration::from_secs(1) / self.max_framerate.max(1))
+    }
+
+    // No point asking for more frames than the interval lets through
+    fn preferred_framerate(&self) -> u32 {
+        if self.frame_interval_ms == 0 {
+            return self.max_framerate;
+        }
+        (1000u64.div_ceil(self.frame_interval_ms) as u32).clamp(1, self.max_framerate)
+    }
+}
+
+pub fn format_name(format: VideoFormat) -> &'static str {
+    match format {
+        VideoFormat::RGB => "RGB",
+        VideoFormat::RGBA => "RGBA",
+        VideoFormat::RGBx => "RGBx",
+        VideoFormat::BGRx => "BGRx",
+        VideoFormat::YUY2 => "YUY2",
+        VideoFormat::I420 => "I420",
+        _ => "other",
+    }
+}
+
+// Accepts the names format_name produces, in any case
+pub fn parse_format(name: &str) -> Option<VideoFormat> {
+    [
+        VideoFormat::BGRx,
+        VideoFormat::RGBx,
+        VideoFormat::RGB,
+        VideoFormat::RGBA,
+        VideoFormat::YUY2,
+        VideoFormat::I420,
+    ]
+    .into_iter()
+    .find(|&format| format_name(format).eq_ignore_ascii_case(name))
+}
+
+// Copies a 4-byte-per-pixel image out of a buffer whose rows are `stride`
+// bytes apart, keeping every `step`th row and column. Sources that can't
+// scale on the compositor side use it to meet the preferred size.
+pub fn copy_thinned(src: &[u8], width: u32, height: u32, stride: usize, step: u32, flip: bool) -> Vec<u8> {
+    let (row_bytes, step) = (width as usize * 4, step.max(1) as usize);
+    let mut data = Vec::with_capacity(row_bytes.div_ceil(step) * (height as usize).div_ceil(step));
+    for y in (0..height as usize).step_by(step) {
+        let row = if flip { height as usize - 1 - y } else { y };
+        let Some(pixels) = src.get(row * stride..row * stride + row_bytes) else {
+            break;
+        };
+        for pixel in pixels.chunks_exact(4).step_by(step) {
+            data.extend_from_slice(pixel);
+        }
+    }
+    data
+}
+
+// Largest whole-number reduction that keeps the image at least as big as
+// the preferred size
+pub fn thinning_step(width: u32, height: u32, settings: &CaptureSettings) -> u32 {
+    (width / settings.preferred_width.max(1))
+        .min(height / settings.preferred_height.max(1))
+        .max(1)
+}
+
+pub fn bytes_per_pixel(format: VideoFormat) -> u32 {
+    match format {
+        VideoFormat::RGB => 3,
+        VideoFormat::RGBA | VideoFormat::RGBx | VideoFormat::BGRx => 4,
+        VideoFormat::YUY2 => 2,
+        VideoFormat::I420 => 1,
+        _ => 4,
+    }
 }
 
 struct UserData {
     format: spa::param::video::VideoInfoRaw,
     last_frame_time: Arc<Mutex<Option<Instant>>>,
     frame_interval: Duration,
-    frame_sender: mpsc::UnboundedSender<FrameData>,
+    frame_sender: FrameSender,
     format_configured: Arc<Mutex<bool>>,
+    mainloop: pw::main_loop::MainLoop,
+    sequence: u64,
+    max_framerate: u32,
+    stream_info: SharedStreamInfo,
+    window_start: Instant,
+    window_frames: u32,
 }
 
 async fn open_portal() -> ashpd::Result<(ScreencastStream, OwnedFd)> {
@@ -54,17 +289,46 @@ async fn open_portal() -> ashpd::Result<(ScreencastStream, OwnedFd)> {
     Ok((stream, fd))
 }
 
-pub async fn start_screen_capture() -> Result<mpsc::UnboundedReceiver<FrameData>, Box<dyn std::error::Error + Send + Sync>> {
+// The screen, shared through the ScreenCast portal and streamed over PipeWire
+#[derive(Default)]
+pub struct PortalSource {
+    stream_info: SharedStreamInfo,
+}
+
+impl FrameSource for PortalSource {
+    fn name(&self) -> &'static str {
+        "portal"
+    }
+
+    fn start<'a>(&'a self, settings: &'a CaptureSettings) -> StartFuture<'a> {
+        *self.stream_info.lock().unwrap() = None;
+        Box::pin(start_screen_capture(settings.clone(), self.stream_info.clone()))
+    }
+
+    // The PipeWire thread quits once the last receiver is gone
+    fn stop(&self) {
+        *self.stream_info.lock().unwrap() = None;
+    }
+
+    fn metadata(&self) -> Option<StreamInfo> {
+        self.stream_info.lock().unwrap().clone()
+    }
+}
+
+async fn start_screen_capture(
+    settings: CaptureSettings,
+    stream_info: SharedStreamInfo,
+) -> Result<FrameReceiver, CaptureError> {
     let (stream, fd) = open_portal().await?;
     let pipewire_node_id = stream.pipe_wire_node_id();
 
-    let (frame_sender, frame_receiver) = mpsc::unbounded_channel();
+    let (frame_sender, frame_receiver) = watch::channel(None);
 
     let sender_clone = frame_sender.clone();
     thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
-            if let Err(e) = start_streaming(pipewire_node_id, fd, sender_clone).await {
+            if let Err(e) = start_streaming(Some(fd), Some(pipewire_node_id), "Screen", settings, stream_info, sender_clone).await {
                 eprintln!("Streaming error: {}", e);
             }
         });
@@ -73,32 +337,62 @@ pub async fn start_screen_capture() -> Result<mpsc::UnboundedReceiver<FrameData>
     Ok(frame_receiver)
 }
 
+// Streams camera frames as fast as they arrive until the receiver is dropped.
+// `remote` is a PipeWire remote from the Camera portal; without one the
+// default daemon is used directly (e.g. for a videotestsrc node).
+pub fn start_camera_capture(remote: Option<OwnedFd>, node_id: Option<u32>) -> FrameReceiver {
+    let (frame_sender, frame_receiver) = watch::channel(None);
+
+    thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            if let Err(e) = start_streaming(remote, node_id, "Camera", CaptureSettings::camera(), Arc::default(), frame_sender).await {
+                eprintln!("Camera streaming error: {}", e);
+            }
+        });
+    });
+
+    frame_receiver
+}
+
 async fn start_streaming(
-    node_id: u32, 
-    fd: OwnedFd, 
-    frame_sender: mpsc::UnboundedSender<FrameData>
+    remote: Option<OwnedFd>,
+    node_id: Option<u32>,
+    role: &'static str,
+    settings: CaptureSettings,
+    stream_info: SharedStreamInfo,
+    frame_sender: FrameSender
 ) -> Result<(), pw::Error> {
     pw::init();
 
     let mainloop = pw::main_loop::MainLoop::new(None)?;
     let context = pw::context::Context::new(&mainloop)?;
-    let core = context.connect_fd(fd, None)?;
+    let core = match remote {
+        Some(fd) => context.connect_fd(fd, None)?,
+        None => context.connect(None)?,
+    };
 
     let data = UserData {
         format: Default::default(),
         last_frame_time: Arc::new(Mutex::new(None)),
-        frame_interval: Duration::from_millis(500),
+        frame_interval: settings.frame_interval(),
         frame_sender,
         format_configured: Arc::new(Mutex::new(false)),
+        mainloop: mainloop.clone(),
+        sequence: 0,
+        max_framerate: settings.max_framerate,
+        stream_info,
+        window_start: Instant::now(),
+        window_frames: 0,
     };
 
     let stream = pw::stream::Stream::new(
         &core,
-        "screen-capture",
+        &format!("{}-capture", role.to_lowercase()),
         properties! {
             *pw::keys::MEDIA_TYPE => "Video",
             *pw::keys::MEDIA_CATEGORY => "Capture",
-            *pw::keys::MEDIA_ROLE => "Screen",
+            *pw::keys::MEDIA_ROLE => role,
         },
     )?;
 
@@ -130,9 +424,36 @@ async fn start_streaming(
                 return;
             }
 
-            if user_data.format.parse(param).is_ok() {
-                *user_data.format_configured.lock().unwrap() = true;
+            if user_data.format.parse(param).is_err() {
+                return;
             }
+            *user_data.format_configured.lock().unwrap() = true;
+
+            // A zero framerate means the source is variable-rate and only
+            // promises its maximum
+            let format = &user_data.format;
+            let nominal = format.framerate();
+            let (framerate, variable_framerate) = if nominal.num > 0 {
+                (nominal.num as f64 / nominal.denom.max(1) as f64, false)
+            } else {
+                let max = format.max_framerate();
+                if max.num > 0 {
+                    (max.num as f64 / max.denom.max(1) as f64, true)
+                } else {
+                    (user_data.max_framerate as f64, true)
+                }
+            };
+            *user_data.stream_info.lock().unwrap() = Some(StreamInfo {
+                format: format_name(format.format()),
+                width: format.size().width,
+                height: format.size().height,
+                framerate,
+                variable_framerate,
+                delivered_fps: 0.0,
+                output: None,
+            });
+            user_data.window_start = Instant::now();
+            user_data.window_frames = 0;
         })
         .process(|stream, user_data| {
             let format_configured = *user_data.format_configured.lock().unwrap();
@@ -173,31 +494,35 @@ async fn start_streaming(
                                 let height = user_data.format.size().height;
                                 let format = user_data.format.format();
                                 
-                                let bytes_per_pixel = match format {
-                                    pw::spa::param::video::VideoFormat::RGB => 3,
-                                    pw::spa::param::video::VideoFormat::RGBA => 4,
-                                    pw::spa::param::video::VideoFormat::RGBx => 4,
-                                    pw::spa::param::video::VideoFormat::BGRx => 4,
-                                    pw::spa::param::video::VideoFormat::YUY2 => 2,
-                                    pw::spa::param::video::VideoFormat::I420 => 1,
-                                    _ => 4,
-                                };
-                                
-                                let expected_size = (width * height * bytes_per_pixel) as usize;
+                                let expected_size = (width * height * bytes_per_pixel(format)) as usize;
                                 let copy_len = std::cmp::min(data_slice.len(), expected_size);
                                 let frame_data_vec = data_slice[..copy_len].to_vec();
 
+                                user_data.sequence += 1;
                                 let frame_data = FrameData {
                                     data: frame_data_vec,
                                     width,
                                     height,
                                     format,
                                     timestamp: now,
+                                    sequence: user_data.sequence,
                                 };
 
-                                if user_data.frame_sender.send(frame_data).is_err() {
+                                // Nobody is listening any more, so shut the stream down
+                                if user_data.frame_sender.send(Some(Arc::new(frame_data))).is_err() {
+                                    user_data.mainloop.quit();
                                     return;
                                 }
+
+                                user_data.window_frames += 1;
+                                let elapsed = now.duration_since(user_data.window_start);
+                                if elapsed >= DELIVERY_WINDOW {
+                                    if let Some(info) = user_data.stream_info.lock().unwrap().as_mut() {
+                                        info.delivered_fps = user_data.window_frames as f64 / elapsed.as_secs_f64();
+                                    }
+                                    user_data.window_start = now;
+                                    user_data.window_frames = 0;
+                                }
                                 
                                 std::thread::sleep(Duration::from_millis(1));
                             }
@@ -240,16 +565,16 @@ async fn start_streaming(
             Range,
             Rectangle,
             pw::spa::utils::Rectangle {
-                width: 1920,
-                height: 1080
+                width: settings.preferred_width,
+                height: settings.preferred_height
             },
             pw::spa::utils::Rectangle {
-                width: 320,
-                height: 240
+                width: settings.min_width,
+                height: settings.min_height
             },
             pw::spa::utils::Rectangle {
-                width: 4096,
-                height: 4096
+                width: settings.max_width,
+                height: settings.max_height
             }
         ),
         pw::spa::pod::property!(
@@ -257,9 +582,9 @@ async fn start_streaming(
             Choice,
             Range,
             Fraction,
-            pw::spa::utils::Fraction { num: 1, denom: 1 },
+            pw::spa::utils::Fraction { num: settings.preferred_framerate(), denom: 1 },
             pw::spa::utils::Fraction { num: 0, denom: 1 },
-            pw::spa::utils::Fraction { num: 60, denom: 1 }
+            pw::spa::utils::Fraction { num: settings.max_framerate, denom: 1 }
         ),
     );
     
@@ -275,7 +600,7 @@ async fn start_streaming(
 
     stream.connect(
         spa::utils::Direction::Input,
-        Some(node_id),
+        node_id,
         pw::stream::StreamFlags::AUTOCONNECT | pw::stream::StreamFlags::MAP_BUFFERS,
         &mut params,
     )?;
@@ -283,4 +608,60 @@ async fn start_streaming(
     mainloop.run();
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_interval_respects_max_framerate() {
+        let settings = |frame_interval_ms, max_framerate| CaptureSettings { frame_interval_ms, max_framerate, ..CaptureSettings::default() };
+        assert_eq!(settings(500, 60).poll_interval(), Duration::from_millis(500));
+        assert_eq!(settings(0, 50).poll_interval(), Duration::from_millis(20));
+        assert_eq!(settings(1, 10).poll_interval(), Duration::from_millis(100));
+        assert_eq!(CaptureSettings::camera().poll_interval(), Duration::from_secs(1) / 30);
+    }
+
+    // A 3x2 image whose pixels are [x, y, row marker, 0xff], in rows padded to 16 bytes
+    fn padded_image() -> Vec<u8> {
+        let mut src = Vec::new();
+        for y in 0..2u8 {
+            for x in 0..3u8 {
+                src.extend_from_slice(&[x, y, 0xa0 + y, 0xff]);
+            }
+            src.extend_from_slice(&[0xee; 4]);
+        }
+        src
+    }
+
+    #[test]
+    fn copy_thinned_skips_row_padding() {
+        let data = copy_thinned(&padded_image(), 3, 2, 16, 1, false);
+        assert_eq!(data.len(), 3 * 2 * 4);
+        assert!(!data.contains(&0xee));
+        assert_eq!(&data[12..16], &[0, 1, 0xa1, 0xff]);
+    }
+
+    #[test]
+    fn copy_thinned_flips_bottom_up_buffers() {
+        let data = copy_thinned(&padded_image(), 3, 2, 16, 1, true);
+        assert_eq!(&data[..4], &[0, 1, 0xa1, 0xff]);
+        assert_eq!(&data[12..16], &[0, 0, 0xa0, 0xff]);
+    }
+
+    #[test]
+    fn copy_thinned_keeps_every_nth_pixel() {
+        let data = copy_thinned(&padded_image(), 3, 2, 16, 2, false);
+        assert_eq!(data, [0, 0, 0xa0, 0xff, 2, 0, 0xa0, 0xff]);
+
+        let flipped = copy_thinned(&padded_image(), 3, 2, 16, 2, true);
+        assert_eq!(flipped, [0, 1, 0xa1, 0xff, 2, 1, 0xa1, 0xff]);
+    }
+
+    #[test]
+    fn copy_thinned_stops_at_a_short_buffer() {
+        let src = padded_image();
+        let data = copy_thinned(&src[..20], 3, 2, 16, 1, false);
+        assert_eq!(data.len(), 3 * 4);
+    }
+}
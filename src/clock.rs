@@ -0,0 +1,88 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+// Local wall-clock hour as a fraction (e.g. 21.5 for 21:30), using the
+// system timezone database through libc.
+pub fn local_hour_of_day() -> f64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as libc::time_t;
+
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::localtime_r(&now, &mut tm) };
+    if result.is_null() {
+        let secs_today = (now as u64) % 86_400;
+        return secs_today as f64 / 3600.0;
+    }
+
+    tm.tm_hour as f64 + tm.tm_min as f64 / 60.0 + tm.tm_sec as f64 / 3600.0
+}
+
+// Hours from now until the local clock next reads `target_hour`; a target
+// equal to the current time counts as a full day away.
+pub fn hours_until_local(target_hour: f64) -> f64 {
+    let delta = (target_hour - local_hour_of_day()).rem_euclid(24.0);
+    if delta == 0.0 { 24.0 } else { delta }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+}
+
+impl LocalTime {
+    // ISO 8601 calendar date, e.g. 2024-03-09
+    pub fn date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+// Local calendar time of a Unix timestamp, falling back to UTC when the
+// timezone can't be resolved
+pub fn local_time(unix_millis: u64) -> LocalTime {
+    let secs = (unix_millis / 1000) as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        unsafe { libc::gmtime_r(&secs, &mut tm) };
+    }
+
+    LocalTime {
+        year: tm.tm_year + 1900,
+        month: (tm.tm_mon + 1) as u32,
+        day: tm.tm_mday as u32,
+        hour: tm.tm_hour as u32,
+    }
+}
+
+// Unix milliseconds of local noon on a YYYY-MM-DD date. Noon keeps whole-day
+// arithmetic clear of daylight saving transitions.
+pub fn local_noon_millis(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<i32>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = year - 1900;
+    tm.tm_mon = month - 1;
+    tm.tm_mday = day;
+    tm.tm_hour = 12;
+    tm.tm_isdst = -1;
+    let secs = unsafe { libc::mktime(&mut tm) };
+    // mktime normalises out-of-range days, e.g. February 30th
+    if secs < 0 || tm.tm_mday != day {
+        return None;
+    }
+    Some(secs as u64 * 1000)
+}
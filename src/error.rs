@@ -0,0 +1,168 @@
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use utoipa::ToSchema;
+use std::fmt;
+
+// A request field that failed validation
+#[derive(Debug, Clone)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self { field, message: message.into() }
+    }
+
+    // For settings validated as part of a larger request, e.g. capture.max_width
+    pub fn within(self, parent: &str) -> ApiError {
+        ApiError::InvalidField { field: format!("{}.{}", parent, self.field), message: self.message }
+    }
+}
+
+impl From<FieldError> for ApiError {
+    fn from(error: FieldError) -> Self {
+        ApiError::InvalidField { field: error.field.to_string(), message: error.message }
+    }
+}
+
+// Every way an API call can fail. Clients should branch on `code()`, which
+// never changes once released; messages are for people and may be reworded.
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    // The body, path or query string couldn't be parsed at all
+    InvalidRequest { message: String },
+    InvalidField { field: String, message: String },
+    NoChanges,
+    // No bearer token, a wrong one, or a used or expired ticket
+    Unauthorized,
+    AlreadyRunning,
+    NotRunning,
+    RecordingInProgress,
+    NoRecording,
+    ProfileNotFound { name: String },
+    RuleNotFound { app: String },
+    CaptureFailed { message: String },
+    // Changing or reading the desktop's display settings failed
+    Backend { action: &'static str, message: String },
+    // Reading or writing our own files failed
+    Storage { action: &'static str, message: String },
+}
+
+// The JSON body of every error response, and of WebSocket command errors
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn invalid(field: &str, message: impl Into<String>) -> Self {
+        ApiError::InvalidField { field: field.to_string(), message: message.into() }
+    }
+
+    pub fn backend(action: &'static str, error: impl fmt::Display) -> Self {
+        ApiError::Backend { action, message: error.to_string() }
+    }
+
+    pub fn storage(action: &'static str, error: impl fmt::Display) -> Self {
+        ApiError::Storage { action, message: error.to_string() }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidRequest { .. } => "invalid_request",
+            ApiError::InvalidField { .. } => "invalid_field",
+            ApiError::NoChanges => "no_changes",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::AlreadyRunning => "already_running",
+            ApiError::NotRunning => "not_running",
+            ApiError::RecordingInProgress => "recording_in_progress",
+            ApiError::NoRecording => "no_recording",
+            ApiError::ProfileNotFound { .. } => "profile_not_found",
+            ApiError::RuleNotFound { .. } => "rule_not_found",
+            ApiError::CaptureFailed { .. } => "capture_failed",
+            ApiError::Backend { .. } => "backend_failed",
+            ApiError::Storage { .. } => "storage_failed",
+        }
+    }
+
+    fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            ApiError::InvalidField { field, .. } => Some(serde_json::json!({ "field": field })),
+            ApiError::ProfileNotFound { name } => Some(serde_json::json!({ "profile": name })),
+            ApiError::RuleNotFound { app } => Some(serde_json::json!({ "app": app })),
+            ApiError::Backend { action, .. } | ApiError::Storage { action, .. } => {
+                Some(serde_json::json!({ "action": action }))
+            },
+            _ => None,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody { code: self.code(), message: self.to_string(), details: self.details() }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidRequest { message } | ApiError::InvalidField { message, .. } => f.write_str(message),
+            ApiError::NoChanges => f.write_str("No valid parameters provided"),
+            ApiError::Unauthorized => f.write_str("Missing or invalid API token"),
+            ApiError::AlreadyRunning => f.write_str("Monitoring is already running"),
+            ApiError::NotRunning => f.write_str("Monitoring is not running"),
+            ApiError::RecordingInProgress => f.write_str("A recording is already running"),
+            ApiError::NoRecording => f.write_str("No recording is running"),
+            ApiError::ProfileNotFound { name } => write!(f, "Profile '{}' not found", name),
+            ApiError::RuleNotFound { app } => write!(f, "No rule for '{}'", app),
+            ApiError::CaptureFailed { message } => write!(f, "Failed to start monitoring: {}", message),
+            ApiError::Backend { action, message } | ApiError::Storage { action, message } => {
+                write!(f, "Failed to {}: {}", action, message)
+            },
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidRequest { .. } | ApiError::InvalidField { .. } | ApiError::NoChanges => {
+                StatusCode::BAD_REQUEST
+            },
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::AlreadyRunning
+            | ApiError::NotRunning
+            | ApiError::RecordingInProgress
+            | ApiError::NoRecording => StatusCode::CONFLICT,
+            ApiError::ProfileNotFound { .. } | ApiError::RuleNotFound { .. } => StatusCode::NOT_FOUND,
+            ApiError::CaptureFailed { .. } | ApiError::Backend { .. } | ApiError::Storage { .. } => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            },
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::Unauthorized = self {
+            response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(self.body())
+    }
+}
+
+// Malformed JSON bodies and query strings get the same shape as every other
+// error instead of actix's plain-text defaults
+pub fn json_config() -> actix_web::web::JsonConfig {
+    actix_web::web::JsonConfig::default()
+        .error_handler(|error, _| ApiError::InvalidRequest { message: error.to_string() }.into())
+}
+
+pub fn query_config() -> actix_web::web::QueryConfig {
+    actix_web::web::QueryConfig::default()
+        .error_handler(|error, _| ApiError::InvalidRequest { message: error.to_string() }.into())
+}
@@ -0,0 +1,166 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+use crate::clock::unix_millis;
+use crate::{FrameAnalysis, NightLightConfig, SystemStatus};
+
+// Events beyond this many are dropped for subscribers that fall behind
+const EVENT_CAPACITY: usize = 256;
+// How far back a reconnecting client can resume from
+const RECENT_EVENTS: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    // Full snapshot, sent to new subscribers
+    Status { status: Box<SystemStatus> },
+    FrameAnalyzed { analysis: FrameAnalysis, frames_processed: u64, frames_dropped: u64 },
+    TemperatureApplied { temperature: u32 },
+    ConfigChanged { config: NightLightConfig },
+    MonitoringStarted { source: &'static str },
+    MonitoringStopped,
+    BackendError { message: String },
+}
+
+impl Event {
+    // Matches the serialized `type` tag
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Status { .. } => "status",
+            Event::FrameAnalyzed { .. } => "frame_analyzed",
+            Event::TemperatureApplied { .. } => "temperature_applied",
+            Event::ConfigChanged { .. } => "config_changed",
+            Event::MonitoringStarted { .. } => "monitoring_started",
+            Event::MonitoringStopped => "monitoring_stopped",
+            Event::BackendError { .. } => "backend_error",
+        }
+    }
+}
+
+// An event with its position in the stream; ids only ever increase, across
+// restarts too
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StampedEvent {
+    pub id: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+struct RecentEvents {
+    next_id: u64,
+    events: VecDeque<StampedEvent>,
+}
+
+// Fans events out to every connected client and keeps the most recent ones
+// so clients can resume after a reconnect. Publishing never blocks.
+pub struct EventBus {
+    sender: broadcast::Sender<StampedEvent>,
+    recent: Mutex<RecentEvents>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        // Ids start from the startup time, so they keep increasing over a
+        // restart as long as fewer than one event a millisecond is published
+        let next_id = unix_millis();
+        Self {
+            sender: broadcast::channel(EVENT_CAPACITY).0,
+            recent: Mutex::new(RecentEvents { next_id, events: VecDeque::with_capacity(RECENT_EVENTS) }),
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Sending under the lock keeps the buffer and the live stream in the
+        // same order, so subscribe_since never sees a gap or a duplicate
+        let mut recent = self.recent.lock().unwrap();
+        let stamped = StampedEvent { id: recent.next_id, event };
+        recent.next_id += 1;
+        if recent.events.len() == RECENT_EVENTS {
+            recent.events.pop_front();
+        }
+        recent.events.push_back(stamped.clone());
+        let _ = self.sender.send(stamped);
+    }
+
+    // Logs a failure in the background pipeline and tells clients about it
+    pub fn backend_error(&self, message: String) {
+        eprintln!("{}", message);
+        self.publish(Event::BackendError { message });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StampedEvent> {
+        self.sender.subscribe()
+    }
+
+    // Buffered events after `last_id`, plus a receiver for everything newer.
+    // None in place of the events means some already fell out of the buffer,
+    // or `last_id` was never issued by this process.
+    pub fn subscribe_since(&self, last_id: u64) -> (Option<Vec<StampedEvent>>, broadcast::Receiver<StampedEvent>) {
+        let recent = self.recent.lock().unwrap();
+        let oldest = recent.events.front().map_or(recent.next_id, |stamped| stamped.id);
+        let missed = (last_id.saturating_add(1) >= oldest && last_id < recent.next_id)
+            .then(|| recent.events.iter().filter(|stamped| stamped.id > last_id).cloned().collect());
+        (missed, self.sender.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(events: &[StampedEvent]) -> Vec<u64> {
+        events.iter().map(|stamped| stamped.id).collect()
+    }
+
+    #[test]
+    fn resumes_from_the_buffer() {
+        let bus = EventBus::new();
+        let first = bus.recent.lock().unwrap().next_id;
+        for temperature in 0..5 {
+            bus.publish(Event::TemperatureApplied { temperature });
+        }
+
+        let (missed, _) = bus.subscribe_since(first + 1);
+        assert_eq!(ids(&missed.unwrap()), vec![first + 2, first + 3, first + 4]);
+
+        // Up to date: nothing missed, but no snapshot needed either
+        let (missed, mut events) = bus.subscribe_since(first + 4);
+        assert!(missed.unwrap().is_empty());
+        bus.publish(Event::MonitoringStopped);
+        assert_eq!(events.try_recv().unwrap().id, first + 5);
+    }
+
+    #[test]
+    fn overrun_buffer_needs_a_snapshot() {
+        let bus = EventBus::new();
+        let first = bus.recent.lock().unwrap().next_id;
+        for temperature in 0..RECENT_EVENTS as u32 + 10 {
+            bus.publish(Event::TemperatureApplied { temperature });
+        }
+
+        // Ten events have fallen out; resuming before them loses some
+        assert!(bus.subscribe_since(first + 8).0.is_none());
+        let (missed, _) = bus.subscribe_since(first + 9);
+        assert_eq!(missed.unwrap().len(), RECENT_EVENTS);
+    }
+
+    #[test]
+    fn ids_from_another_run_need_a_snapshot() {
+        let bus = EventBus::new();
+        let first = bus.recent.lock().unwrap().next_id;
+        assert!(first >= unix_millis() - 1000);
+
+        // Before any event, and after publishing some
+        assert!(bus.subscribe_since(first).0.is_none());
+        bus.publish(Event::MonitoringStopped);
+        bus.publish(Event::MonitoringStopped);
+
+        // From an earlier run, and from a later one
+        assert!(bus.subscribe_since(1).0.is_none());
+        assert!(bus.subscribe_since(first + 2).0.is_none());
+        assert!(bus.subscribe_since(u64::MAX).0.is_none());
+    }
+}
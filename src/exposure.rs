@@ -0,0 +1,309 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use crate::clock::{local_noon_millis, local_time, unix_millis};
+use crate::storage::{load_json, save_json};
+use crate::FrameAnalysis;
+
+// A pause between analyses longer than twice the capture interval (and at
+// least this) means the screen was off or monitoring was stopped, so it
+// doesn't count as screen time
+const MIN_MAX_GAP_MS: u64 = 5_000;
+// Days run from 04:00 to 04:00 local time, so a late night belongs to the
+// evening it started on. Evening is 18:00 until the end of that day.
+const DAY_START_HOUR: u32 = 4;
+const EVENING_START_HOUR: u32 = 18;
+const KEEP_DAYS: usize = 400;
+const SAVE_INTERVAL_MS: u64 = 60_000;
+const DAY_MS: u64 = 86_400_000;
+
+// Screen time and blue-light dose. The dose is in minutes at full blue
+// intensity: a minute in front of a screen whose blue channel averages 255
+// adds 1.0, a minute at half that adds 0.5.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, ToSchema)]
+pub struct Exposure {
+    pub screen_minutes: f64,
+    pub dose: f64,
+}
+
+impl Exposure {
+    fn add(&mut self, other: &Exposure) {
+        self.screen_minutes += other.screen_minutes;
+        self.dose += other.dose;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DayExposure {
+    pub date: String,
+    pub total: Exposure,
+    pub evening: Exposure,
+    // Indexed by local hour of day
+    pub hours: Vec<Exposure>,
+    // Keyed by the focused application's id, where one was known
+    pub applications: BTreeMap<String, Exposure>,
+}
+
+impl DayExposure {
+    fn new(date: String) -> Self {
+        Self {
+            date,
+            total: Exposure::default(),
+            evening: Exposure::default(),
+            hours: vec![Exposure::default(); 24],
+            applications: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WeeklyReport {
+    pub from: String,
+    pub to: String,
+    pub total: Exposure,
+    pub evening: Exposure,
+    pub applications: BTreeMap<String, Exposure>,
+    pub days: Vec<DayExposure>,
+}
+
+// Integrates screen time and blue-light dose per day from the stream of
+// analyses. Totals are saved to disk at most once a minute.
+pub struct ExposureTracker {
+    days: BTreeMap<String, DayExposure>,
+    last_analysis_at: Option<u64>,
+    last_saved_at: u64,
+    storage_path: PathBuf,
+}
+
+// The day a timestamp counts towards, and whether it falls in the evening
+fn exposure_day(at: u64) -> (String, u32, bool) {
+    let hour = local_time(at).hour;
+    let date = local_time(at.saturating_sub(DAY_START_HOUR as u64 * 3_600_000)).date();
+    (date, hour, !(DAY_START_HOUR..EVENING_START_HOUR).contains(&hour))
+}
+
+pub fn exposure_date(at: u64) -> String {
+    exposure_day(at).0
+}
+
+impl ExposureTracker {
+    pub fn load(storage_path: PathBuf) -> Self {
+        let days = match load_json::<Vec<DayExposure>>(&storage_path) {
+            Ok(days) => days.unwrap_or_default(),
+            Err(e) => {
+                eprintln!("Ignoring unreadable exposure history {}: {}", storage_path.display(), e);
+                Vec::new()
+            }
+        };
+
+        Self {
+            days: days.into_iter().map(|day| (day.date.clone(), day)).collect(),
+            last_analysis_at: None,
+            last_saved_at: unix_millis(),
+            storage_path,
+        }
+    }
+
+    pub fn day(&self, date: &str) -> Option<&DayExposure> {
+        self.days.get(date)
+    }
+
+    pub fn record(&mut self, analysis: &FrameAnalysis, focused_app: Option<&str>, frame_interval_ms: u64) {
+        let at = analysis.timestamp;
+        let elapsed = self.last_analysis_at.map_or(0, |last| at.saturating_sub(last));
+        self.last_analysis_at = Some(at);
+        let max_gap_ms = frame_interval_ms.saturating_mul(2).max(MIN_MAX_GAP_MS);
+        if elapsed == 0 || elapsed > max_gap_ms {
+            return;
+        }
+
+        let minutes = elapsed as f64 / 60_000.0;
+        let exposure = Exposure {
+            screen_minutes: minutes,
+            dose: minutes * (analysis.blue_intensity / 255.0).clamp(0.0, 1.0),
+        };
+
+        let (date, hour, evening) = exposure_day(at);
+        let day = self.days.entry(date.clone()).or_insert_with(|| DayExposure::new(date));
+        day.total.add(&exposure);
+        day.hours[hour as usize].add(&exposure);
+        if evening {
+            day.evening.add(&exposure);
+        }
+        if let Some(app) = focused_app {
+            day.applications.entry(app.to_string()).or_default().add(&exposure);
+        }
+
+        while self.days.len() > KEEP_DAYS {
+            self.days.pop_first();
+        }
+
+        if at.saturating_sub(self.last_saved_at) >= SAVE_INTERVAL_MS {
+            self.last_saved_at = at;
+            self.save();
+        }
+    }
+
+    // Writes out what the last minute added, e.g. on shutdown
+    pub fn flush(&mut self) {
+        self.last_saved_at = unix_millis();
+        self.save();
+    }
+
+    fn save(&self) {
+        let days: Vec<&DayExposure> = self.days.values().collect();
+        if let Err(e) = save_json(&self.storage_path, &days) {
+            eprintln!("Failed to save exposure history {}: {}", self.storage_path.display(), e);
+        }
+    }
+
+    // The day containing `date`, or today; days without screen time are empty
+    pub fn daily(&self, date: Option<&str>) -> Option<DayExposure> {
+        let date = match date {
+            Some(date) => {
+                local_noon_millis(date)?;
+                date.to_string()
+            },
+            None => exposure_date(unix_millis()),
+        };
+        Some(self.days.get(&date).cloned().unwrap_or_else(|| DayExposure::new(date)))
+    }
+
+    // The seven days ending on `end`, or on today
+    pub fn weekly(&self, end: Option<&str>) -> Option<WeeklyReport> {
+        let end_noon = match end {
+            Some(end) => local_noon_millis(end)?,
+            None => local_noon_millis(&exposure_date(unix_millis()))?,
+        };
+
+        let days: Vec<DayExposure> = (0..7)
+            .rev()
+            .map(|back| {
+                let date = local_time(end_noon - back * DAY_MS).date();
+                self.days.get(&date).cloned().unwrap_or_else(|| DayExposure::new(date))
+            })
+            .collect();
+
+        let mut report = WeeklyReport {
+            from: days[0].date.clone(),
+            to: days[6].date.clone(),
+            total: Exposure::default(),
+            evening: Exposure::default(),
+            applications: BTreeMap::new(),
+            days: Vec::new(),
+        };
+        for day in &days {
+            report.total.add(&day.total);
+            report.evening.add(&day.evening);
+            for (app, exposure) in &day.applications {
+                report.applications.entry(app.clone()).or_default().add(exposure);
+            }
+        }
+        report.days = days;
+        Some(report)
+    }
+}
+
+// Application ids come from the compositor; quote anything CSV-unsafe
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// One row per hour of the day, then one per application
+pub fn daily_csv(day: &DayExposure) -> String {
+    let mut csv = String::from("date,breakdown,key,screen_minutes,dose\n");
+    for (hour, exposure) in day.hours.iter().enumerate() {
+        let _ = writeln!(csv, "{},hour,{:02},{:.3},{:.4}", day.date, hour, exposure.screen_minutes, exposure.dose);
+    }
+    for (app, exposure) in &day.applications {
+        let _ = writeln!(
+            csv,
+            "{},application,{},{:.3},{:.4}",
+            day.date,
+            csv_field(app),
+            exposure.screen_minutes,
+            exposure.dose
+        );
+    }
+    csv
+}
+
+pub fn weekly_csv(report: &WeeklyReport) -> String {
+    let mut csv = String::from("date,screen_minutes,dose,evening_screen_minutes,evening_dose\n");
+    for day in &report.days {
+        let _ = writeln!(
+            csv,
+            "{},{:.3},{:.4},{:.3},{:.4}",
+            day.date, day.total.screen_minutes, day.total.dose, day.evening.screen_minutes, day.evening.dose
+        );
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analysis_at(timestamp: u64) -> FrameAnalysis {
+        FrameAnalysis {
+            average_brightness: 128.0,
+            blue_intensity: 255.0,
+            ambient_light_level: 0.5,
+            ambient_source: "screen",
+            ambient_lux: None,
+            motion_level: 0.0,
+            timestamp,
+            frame_size: 0,
+        }
+    }
+
+    fn screen_minutes(tracker: &ExposureTracker, at: u64) -> f64 {
+        tracker.day(&exposure_date(at)).map_or(0.0, |day| day.total.screen_minutes)
+    }
+
+    #[test]
+    fn gaps_up_to_twice_the_capture_interval_count() {
+        let mut tracker = ExposureTracker::load(crate::storage::test_dir("exposure-gaps").join("exposure.json"));
+        let start = local_noon_millis("2026-03-02").unwrap();
+
+        // Frames every 10 s under a 10 s interval are all screen time
+        for i in 0..=6 {
+            tracker.record(&analysis_at(start + i * 10_000), Some("firefox"), 10_000);
+        }
+        assert!((screen_minutes(&tracker, start) - 1.0).abs() < 1e-9);
+
+        // Half a minute without a frame is more than 2 x 10 s: the screen was off
+        tracker.record(&analysis_at(start + 90_000), None, 10_000);
+        assert!((screen_minutes(&tracker, start) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fast_capture_still_tolerates_short_pauses() {
+        let mut tracker = ExposureTracker::load(crate::storage::test_dir("exposure-fast").join("exposure.json"));
+        let start = local_noon_millis("2026-03-02").unwrap();
+        tracker.record(&analysis_at(start), None, 100);
+        tracker.record(&analysis_at(start + 3_000), None, 100);
+        assert!((screen_minutes(&tracker, start) - 0.05).abs() < 1e-9);
+    }
+
+    #[test]
+    fn flush_saves_unsaved_time() {
+        let path = crate::storage::test_dir("exposure-flush").join("exposure.json");
+        let mut tracker = ExposureTracker::load(path.clone());
+        let start = unix_millis();
+        tracker.record(&analysis_at(start), None, 1_000);
+        tracker.record(&analysis_at(start + 1_000), None, 1_000);
+        assert!(!path.exists());
+
+        tracker.flush();
+        let reloaded = ExposureTracker::load(path);
+        assert!(screen_minutes(&reloaded, start + 1_000) > 0.0);
+    }
+}
@@ -0,0 +1,442 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use crate::clock::unix_millis;
+use crate::events::{Event, StampedEvent};
+use crate::FrameAnalysis;
+
+const MINUTE_MS: u64 = 60_000;
+const HOUR_MS: u64 = 60 * MINUTE_MS;
+const DAY_MS: u64 = 24 * HOUR_MS;
+
+// Downsampling: entries older than the first value are merged into buckets
+// of the second. Anything newer than an hour is kept as recorded.
+const TIERS: [(u64, u64); 2] = [(HOUR_MS, MINUTE_MS), (7 * DAY_MS, HOUR_MS)];
+const DEFAULT_RETENTION_DAYS: u64 = 90;
+const COMPACT_INTERVAL: Duration = Duration::from_secs(600);
+// Queries asking for more buckets than this are refused
+pub const MAX_POINTS: u64 = 2000;
+
+// Running aggregate of one metric
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Stat {
+    count: u32,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Stat {
+    fn single(value: f64) -> Self {
+        Self { count: 1, sum: value, min: value, max: value }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn merge(&mut self, other: &Stat) {
+        if other.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = *other;
+            return;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    fn summary(&self) -> Option<Summary> {
+        (!self.is_empty()).then(|| Summary { mean: self.sum / self.count as f64, min: self.min, max: self.max })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+pub struct Summary {
+    pub mean: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+// One line of the history file. A freshly recorded sample has a span of 0;
+// downsampling merges samples into buckets covering `span` milliseconds.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Entry {
+    start: u64,
+    span: u64,
+    #[serde(default, skip_serializing_if = "Stat::is_empty")]
+    brightness: Stat,
+    #[serde(default, skip_serializing_if = "Stat::is_empty")]
+    blue_intensity: Stat,
+    #[serde(default, skip_serializing_if = "Stat::is_empty")]
+    ambient_light: Stat,
+    #[serde(default, skip_serializing_if = "Stat::is_empty")]
+    motion: Stat,
+    #[serde(default, skip_serializing_if = "Stat::is_empty")]
+    temperature: Stat,
+}
+
+impl Entry {
+    fn merge(&mut self, other: &Entry) {
+        self.brightness.merge(&other.brightness);
+        self.blue_intensity.merge(&other.blue_intensity);
+        self.ambient_light.merge(&other.ambient_light);
+        self.motion.merge(&other.motion);
+        self.temperature.merge(&other.temperature);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HistoryPoint {
+    pub timestamp: u64,
+    pub average_brightness: Option<Summary>,
+    pub blue_intensity: Option<Summary>,
+    pub ambient_light_level: Option<Summary>,
+    pub motion_level: Option<Summary>,
+    pub temperature: Option<Summary>,
+}
+
+// Time series of analyses and applied temperatures, appended to a JSON lines
+// file as they happen. Old entries are periodically downsampled and anything
+// past the retention period (LUMINA_HISTORY_RETENTION_DAYS, 90 by default)
+// is dropped, rewriting the file.
+pub struct HistoryStore {
+    entries: VecDeque<Entry>,
+    retention_ms: u64,
+    storage_path: PathBuf,
+    file: Option<File>,
+}
+
+impl HistoryStore {
+    pub fn load(storage_path: PathBuf) -> Self {
+        let retention_days = std::env::var("LUMINA_HISTORY_RETENTION_DAYS")
+            .ok()
+            .and_then(|days| days.parse::<u64>().ok())
+            .filter(|days| *days > 0)
+            .unwrap_or(DEFAULT_RETENTION_DAYS);
+
+        let mut entries = match read_entries(&storage_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Ignoring unreadable history {}: {}", storage_path.display(), e);
+                Vec::new()
+            }
+        };
+        entries.sort_by_key(|entry| entry.start);
+
+        let mut store = Self {
+            entries: entries.into(),
+            retention_ms: retention_days * DAY_MS,
+            storage_path,
+            file: None,
+        };
+        store.compact(unix_millis());
+        store
+    }
+
+    pub fn record_analysis(&mut self, analysis: &FrameAnalysis) {
+        self.append(Entry {
+            start: analysis.timestamp,
+            brightness: Stat::single(analysis.average_brightness),
+            blue_intensity: Stat::single(analysis.blue_intensity),
+            ambient_light: Stat::single(analysis.ambient_light_level),
+            motion: Stat::single(analysis.motion_level),
+            ..Entry::default()
+        });
+    }
+
+    pub fn record_temperature(&mut self, at: u64, temperature: u32) {
+        self.append(Entry { start: at, temperature: Stat::single(temperature as f64), ..Entry::default() });
+    }
+
+    fn append(&mut self, entry: Entry) {
+        if self.file.is_none() {
+            self.file = open_append(&self.storage_path)
+                .map_err(|e| eprintln!("Failed to open history {}: {}", self.storage_path.display(), e))
+                .ok();
+        }
+        if let Some(file) = self.file.as_mut() {
+            let mut line = serde_json::to_vec(&entry).expect("history entries serialize to JSON");
+            line.push(b'\n');
+            if let Err(e) = file.write_all(&line) {
+                eprintln!("Failed to append to history {}: {}", self.storage_path.display(), e);
+                self.file = None;
+            }
+        }
+
+        // Clocks can step backwards; keep the series ordered regardless
+        let position = self.entries.partition_point(|existing| existing.start <= entry.start);
+        self.entries.insert(position, entry);
+    }
+
+    // Applies the retention and downsampling policies and rewrites the file
+    pub fn compact(&mut self, now: u64) {
+        let cutoff = now.saturating_sub(self.retention_ms);
+        let mut compacted: VecDeque<Entry> = VecDeque::with_capacity(self.entries.len());
+
+        for entry in self.entries.drain(..).filter(|entry| entry.start + entry.span >= cutoff) {
+            let age = now.saturating_sub(entry.start);
+            let span = TIERS
+                .iter()
+                .filter(|(after, _)| age >= *after)
+                .map(|(_, span)| *span)
+                .max()
+                .unwrap_or(0);
+            if entry.span >= span {
+                compacted.push_back(entry);
+                continue;
+            }
+
+            let start = entry.start - entry.start % span;
+            match compacted.back_mut() {
+                Some(bucket) if bucket.start == start && bucket.span == span => bucket.merge(&entry),
+                _ => compacted.push_back(Entry { start, span, ..entry }),
+            }
+        }
+        self.entries = compacted;
+
+        if let Err(e) = self.rewrite() {
+            eprintln!("Failed to rewrite history {}: {}", self.storage_path.display(), e);
+        }
+    }
+
+    // Writes to a temporary sibling first so a crash never loses the history
+    fn rewrite(&mut self) -> io::Result<()> {
+        self.file = None;
+        if let Some(parent) = self.storage_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let tmp_path = self.storage_path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        for entry in &self.entries {
+            serde_json::to_writer(&mut writer, entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            writer.write_all(b"\n")?;
+        }
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp_path, &self.storage_path)?;
+
+        self.file = Some(open_append(&self.storage_path)?);
+        Ok(())
+    }
+
+    // Aggregates everything in [from, to) into buckets of `resolution`
+    // milliseconds, aligned to the epoch. Buckets without data are omitted.
+    pub fn query(&self, from: u64, to: u64, resolution: u64) -> Vec<HistoryPoint> {
+        let first = self.entries.partition_point(|entry| entry.start < from);
+        let mut buckets: Vec<Entry> = Vec::new();
+
+        for entry in self.entries.range(first..).take_while(|entry| entry.start < to) {
+            let start = entry.start - entry.start % resolution;
+            match buckets.last_mut() {
+                Some(bucket) if bucket.start == start => bucket.merge(entry),
+                _ => buckets.push(Entry { start, span: resolution, ..entry.clone() }),
+            }
+        }
+
+        buckets
+            .iter()
+            .map(|bucket| HistoryPoint {
+                timestamp: bucket.start,
+                average_brightness: bucket.brightness.summary(),
+                blue_intensity: bucket.blue_intensity.summary(),
+                ambient_light_level: bucket.ambient_light.summary(),
+                motion_level: bucket.motion.summary(),
+                temperature: bucket.temperature.summary(),
+            })
+            .collect()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// A crash can leave a torn last line; skip lines that don't parse
+fn read_entries(path: &Path) -> io::Result<Vec<Entry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(entry) = serde_json::from_str::<Entry>(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+// Feeds the store from the event bus and compacts it every few minutes
+pub async fn record_events(history: Arc<Mutex<HistoryStore>>, mut events: Receiver<StampedEvent>) {
+    let mut compact = tokio::time::interval(COMPACT_INTERVAL);
+    compact.tick().await; // The store was compacted when it was loaded
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(stamped) => match stamped.event {
+                    Event::FrameAnalyzed { analysis, .. } => history.lock().unwrap().record_analysis(&analysis),
+                    Event::TemperatureApplied { temperature } => {
+                        history.lock().unwrap().record_temperature(unix_millis(), temperature);
+                    },
+                    _ => {},
+                },
+                // A few analyses missing from the history don't matter
+                Err(RecvError::Lagged(_)) => {},
+                Err(RecvError::Closed) => return,
+            },
+            _ = compact.tick() => history.lock().unwrap().compact(unix_millis()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // On an hour boundary, so the buckets below are easy to place
+    const NOW: u64 = 480_000 * HOUR_MS;
+
+    fn store(name: &str) -> HistoryStore {
+        HistoryStore::load(crate::storage::test_dir(name).join("history.jsonl"))
+    }
+
+    fn spans(store: &HistoryStore) -> Vec<(u64, u64, u32)> {
+        store.entries.iter().map(|entry| (entry.start, entry.span, entry.temperature.count)).collect()
+    }
+
+    fn temperature(point: &HistoryPoint) -> (f64, f64, f64) {
+        let summary = point.temperature.unwrap();
+        (summary.mean, summary.min, summary.max)
+    }
+
+    #[test]
+    fn compaction_downsamples_by_age_and_drops_expired() {
+        let mut store = store("history-compact");
+        let two_hours_ago = NOW - 2 * HOUR_MS;
+        let eight_days_ago = NOW - 8 * DAY_MS;
+        let planted = [
+            // Past retention
+            (NOW - 91 * DAY_MS, 1000),
+            // Hour buckets past a week
+            (eight_days_ago + MINUTE_MS, 2000),
+            (eight_days_ago + 30 * MINUTE_MS, 2600),
+            (eight_days_ago + 59 * MINUTE_MS, 3200),
+            (eight_days_ago + HOUR_MS, 5000),
+            // Minute buckets past an hour
+            (two_hours_ago + 1_000, 3000),
+            (two_hours_ago + 20_000, 3300),
+            (two_hours_ago + 59_000, 3600),
+            (two_hours_ago + MINUTE_MS, 4000),
+            // Exactly an hour old is the first age to be bucketed
+            (NOW - HOUR_MS, 4100),
+            // Raw within the hour
+            (NOW - HOUR_MS + 1, 4200),
+            (NOW - 5 * MINUTE_MS, 4300),
+            (NOW - 5 * MINUTE_MS + 1, 4400),
+        ];
+        for (at, value) in planted {
+            store.record_temperature(at, value);
+        }
+
+        store.compact(NOW);
+        let expected = vec![
+            (eight_days_ago, HOUR_MS, 3),
+            (eight_days_ago + HOUR_MS, HOUR_MS, 1),
+            (two_hours_ago, MINUTE_MS, 3),
+            (two_hours_ago + MINUTE_MS, MINUTE_MS, 1),
+            (NOW - HOUR_MS, MINUTE_MS, 1),
+            (NOW - HOUR_MS + 1, 0, 1),
+            (NOW - 5 * MINUTE_MS, 0, 1),
+            (NOW - 5 * MINUTE_MS + 1, 0, 1),
+        ];
+        assert_eq!(spans(&store), expected);
+
+        let bucket = &store.entries[0].temperature;
+        assert_eq!((bucket.sum / bucket.count as f64, bucket.min, bucket.max), (2600.0, 2000.0, 3200.0));
+
+        // Compacting again changes nothing, and the file holds the same
+        store.compact(NOW);
+        assert_eq!(spans(&store), expected);
+        let on_disk = read_entries(&store.storage_path).unwrap();
+        assert_eq!(on_disk.len(), expected.len());
+        assert_eq!(on_disk[2].temperature.count, 3);
+    }
+
+    #[test]
+    fn ageing_buckets_merge_into_coarser_ones() {
+        let mut store = store("history-ageing");
+        let start = NOW - 2 * HOUR_MS;
+        for minute in 0..60 {
+            store.record_temperature(start + minute * MINUTE_MS + 1_000, 3000 + minute as u32);
+        }
+        store.compact(NOW);
+        assert_eq!(store.entries.len(), 60);
+
+        // A week later the minute buckets fold into one hour
+        store.compact(NOW + 7 * DAY_MS);
+        assert_eq!(spans(&store), vec![(start, HOUR_MS, 60)]);
+        assert_eq!(store.entries[0].temperature.min, 3000.0);
+        assert_eq!(store.entries[0].temperature.max, 3059.0);
+
+        store.compact(NOW + 91 * DAY_MS);
+        assert!(store.entries.is_empty());
+    }
+
+    #[test]
+    fn query_buckets_within_the_range() {
+        let mut store = store("history-query");
+        for (at, value) in [
+            (NOW - 3 * HOUR_MS - 1, 1000),
+            (NOW - 3 * HOUR_MS, 2000),
+            (NOW - 3 * HOUR_MS + 10 * MINUTE_MS, 3000),
+            (NOW - HOUR_MS + 1, 4000),
+            (NOW - 1, 5000),
+            (NOW, 6000),
+        ] {
+            store.record_temperature(at, value);
+        }
+        store.record_analysis(&FrameAnalysis {
+            average_brightness: 80.0,
+            blue_intensity: 0.5,
+            ambient_light_level: 0.25,
+            ambient_source: "screen",
+            ambient_lux: None,
+            motion_level: 0.1,
+            timestamp: NOW - 30 * MINUTE_MS,
+            frame_size: 0,
+        });
+
+        // [from, to) with empty hours left out
+        let points = store.query(NOW - 3 * HOUR_MS, NOW, HOUR_MS);
+        let timestamps: Vec<u64> = points.iter().map(|point| point.timestamp).collect();
+        assert_eq!(timestamps, vec![NOW - 3 * HOUR_MS, NOW - HOUR_MS]);
+        assert_eq!(temperature(&points[0]), (2500.0, 2000.0, 3000.0));
+        assert!(points[0].average_brightness.is_none());
+        assert_eq!(temperature(&points[1]), (4500.0, 4000.0, 5000.0));
+        assert_eq!(points[1].average_brightness.unwrap().mean, 80.0);
+        assert_eq!(points[1].ambient_light_level.unwrap().max, 0.25);
+
+        // NOW starts a new day, so the whole range is one daily bucket
+        let points = store.query(0, NOW, DAY_MS);
+        assert_eq!(points.len(), 1);
+        assert_eq!(temperature(&points[0]), (3000.0, 1000.0, 5000.0));
+        assert_eq!(store.query(0, NOW + 1, DAY_MS).len(), 2);
+    }
+}
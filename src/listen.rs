@@ -0,0 +1,167 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+
+// Where the API accepts connections, from LUMINA_LISTEN: a comma-separated
+// list of host:port and unix[:path] entries, e.g. "127.0.0.1:9090,unix".
+// A bare "unix" means $XDG_RUNTIME_DIR/lumina.sock.
+#[derive(Debug, Clone)]
+pub enum Listen {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listen::Tcp(address) => write!(f, "http://{}", address),
+            Listen::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+fn default_socket_path() -> Result<PathBuf, String> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join("lumina.sock"))
+        .ok_or_else(|| "XDG_RUNTIME_DIR is not set; give the socket path as unix:/path/to/lumina.sock".to_string())
+}
+
+fn parse_entry(entry: &str) -> Result<Listen, String> {
+    match entry.strip_prefix("unix") {
+        Some("") => default_socket_path().map(Listen::Unix),
+        Some(rest) => match rest.strip_prefix(':') {
+            Some(path) if !path.is_empty() => Ok(Listen::Unix(PathBuf::from(path))),
+            _ => Err(format!("Invalid listen address '{}' (use unix or unix:/path)", entry)),
+        },
+        // Only checks the shape; binding reports anything else
+        None => match entry.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(Listen::Tcp(entry.to_string())),
+            _ => Err(format!("Invalid listen address '{}' (use host:port, e.g. 127.0.0.1:8080)", entry)),
+        },
+    }
+}
+
+pub fn from_env() -> Result<Vec<Listen>, String> {
+    let value = std::env::var("LUMINA_LISTEN").ok().filter(|value| !value.trim().is_empty());
+    value
+        .as_deref()
+        .unwrap_or(DEFAULT_LISTEN)
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(parse_entry)
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|listeners| match listeners.is_empty() {
+            true => Err("LUMINA_LISTEN has no addresses".to_string()),
+            false => Ok(listeners),
+        })
+}
+
+// Binds the socket readable and writable by its owner only. That is the
+// access control: requests arriving on it need no API token. The default
+// location is inside XDG_RUNTIME_DIR, which is private to the user anyway.
+pub fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            // Left behind by an instance that didn't shut down cleanly
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, "another instance is listening on this socket"));
+            }
+            fs::remove_file(path)?;
+        },
+        // The rename below would replace it without complaint
+        Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "a file that isn't a socket is in the way")),
+        Err(_) => {},
+    }
+
+    // bind creates the socket with the umask's mode, so it's made inside a
+    // directory only we can enter, restricted, then moved into place. The
+    // umask itself is process-wide and other threads are already running.
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "socket path has no file name"))?;
+    let staging = parent.join(format!(".{}.{}", name.to_string_lossy(), std::process::id()));
+    let staged = staging.join(name);
+    // Left behind by an earlier process with the same pid
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&staging);
+    fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+    let result = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&staged);
+    let _ = fs::remove_dir(&staging);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(entry: &str) -> Option<String> {
+        match parse_entry(entry) {
+            Ok(Listen::Tcp(address)) => Some(address),
+            _ => None,
+        }
+    }
+
+    fn unix(entry: &str) -> Option<PathBuf> {
+        match parse_entry(entry) {
+            Ok(Listen::Unix(path)) => Some(path),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_unix_entries() {
+        assert_eq!(unix("unix:/run/lumina.sock"), Some(PathBuf::from("/run/lumina.sock")));
+        assert_eq!(unix("unix:relative.sock"), Some(PathBuf::from("relative.sock")));
+        assert!(parse_entry("unix:").is_err());
+        assert!(parse_entry("unixy").is_err());
+
+        // A bare "unix" follows XDG_RUNTIME_DIR, or fails without it
+        match default_socket_path() {
+            Ok(path) => assert_eq!(unix("unix"), Some(path)),
+            Err(_) => assert!(parse_entry("unix").is_err()),
+        }
+    }
+
+    #[test]
+    fn parses_tcp_entries() {
+        assert_eq!(tcp("127.0.0.1:8080").as_deref(), Some("127.0.0.1:8080"));
+        assert_eq!(tcp("localhost:9090").as_deref(), Some("localhost:9090"));
+        assert_eq!(tcp("[::1]:8080").as_deref(), Some("[::1]:8080"));
+
+        for entry in ["127.0.0.1", "127.0.0.1:", ":8080", "127.0.0.1:http", "127.0.0.1:65536", "127.0.0.1:-1"] {
+            assert!(parse_entry(entry).is_err(), "{}", entry);
+        }
+    }
+
+    #[test]
+    fn socket_is_private_and_stale_ones_are_replaced() {
+        let dir = crate::storage::test_dir("listen-socket");
+        let path = dir.join("lumina.sock");
+        let listener = bind_unix(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        UnixStream::connect(&path).unwrap();
+        // Nothing is left behind from staging
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(bind_unix(&path).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+
+        drop(listener);
+        bind_unix(&path).unwrap();
+
+        let file = dir.join("notes.txt");
+        fs::write(&file, "keep").unwrap();
+        assert_eq!(bind_unix(&file).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep");
+    }
+}
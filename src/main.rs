@@ -1,50 +1,168 @@
 use actix_web::{web, App, HttpServer, HttpResponse, Result, middleware::Logger};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
-use std::process::Command;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
 use actix_cors::Cors;
 
+mod ambient;
+mod auth;
 mod capture;
-use capture::{start_screen_capture, FrameData};
+mod clock;
+mod error;
+mod events;
+mod exposure;
+mod history;
+mod listen;
+mod metrics;
+mod motion;
+mod openapi;
+mod preferences;
+mod profiles;
+mod recording;
+mod rules;
+mod screencopy;
+mod sleep;
+mod sse;
+mod storage;
+mod synthetic;
+mod window_tracker;
+mod ws;
+mod x11_shm;
+use ambient::{fresh_reading, run_ambient_sensors, SharedAmbientReading};
+use auth::Auth;
+use capture::{select_frame_source, CaptureSettings, FrameData, FrameReceiver, FrameSource, StreamInfo};
+use clock::{hours_until_local, local_hour_of_day, unix_millis};
+use error::{ApiError, ErrorBody};
+use events::{Event, EventBus};
+use exposure::ExposureTracker;
+use history::HistoryStore;
+use metrics::{BackendCall, METRICS};
+use motion::MotionDetector;
+use preferences::PreferenceLearner;
+use profiles::{Profile, ProfileStore, TemperaturePolicy};
+use recording::FrameRecorder;
+use rules::{RuleAction, RuleStore};
+use sleep::SleepJournal;
+use window_tracker::{detect_window_tracker, WindowTracker};
+use utoipa_redoc::{Redoc, Servable};
 
 // Re-using the structs and functions from your main application
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 struct NightLightConfig {
     temperature: u32,
     enabled: bool,
+    adaptation_pause_secs: u64,
+    manual_override: Option<ManualOverride>,
+    active_profile: Option<String>,
+    min_temperature: u32,
+    max_temperature: u32,
+    policy: TemperaturePolicy,
+    smoothing: f64,
+    freeze_on_fullscreen: bool,
+    freeze_on_motion: bool,
+    motion_threshold: f64,
+    capture: CaptureSettings,
 }
 
-#[derive(Debug, Clone, Serialize)] // Added Clone trait
+// Holds a fixed temperature and suspends auto-adjustment until it expires
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+struct ManualOverride {
+    temperature: u32,
+    expires_at: u64, // Unix timestamp in milliseconds
+    until_next_transition: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)] // Added Clone trait
 struct FrameAnalysis {
     average_brightness: f64,
     blue_intensity: f64,
     ambient_light_level: f64,
+    ambient_source: &'static str,
+    ambient_lux: Option<f64>,
+    motion_level: f64,
     timestamp: u64, // Unix timestamp in milliseconds
     frame_size: usize,
 }
 
-#[derive(Debug, Clone, Serialize)] // Added Clone trait
+#[derive(Debug, Clone, Serialize, ToSchema)] // Added Clone trait
 struct SystemStatus {
     running: bool,
     frames_processed: u64,
+    frames_dropped: u64,
     current_analysis: Option<FrameAnalysis>,
     current_config: NightLightConfig,
     last_update: u64,
+    override_remaining_secs: Option<u64>,
+    capture_source: &'static str,
+    focused_app: Option<String>,
+    fullscreen: bool,
+    high_motion: bool,
+    adjustments_frozen: bool,
+    capture_stream: Option<StreamInfo>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct UpdateConfigRequest {
     temperature: Option<u32>,
     enabled: Option<bool>,
+    adaptation_pause_secs: Option<u64>,
+    freeze_on_fullscreen: Option<bool>,
+    freeze_on_motion: Option<bool>,
+    motion_threshold: Option<f64>,
+    capture: Option<CaptureSettings>, // Takes effect the next time monitoring starts
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct OverrideRequest {
+    temperature: u32,
+    duration_minutes: Option<u64>,
+    until_next_transition: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct RecordingRequest {
+    // A file name inside the recordings directory; defaults to a timestamp
+    name: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RecordingStarted {
+    #[schema(value_type = String)]
+    path: std::path::PathBuf,
 }
 
 // Global application state
 struct AppState {
     status: Arc<Mutex<SystemStatus>>,
     config: Arc<Mutex<NightLightConfig>>,
-    frame_receiver: Arc<Mutex<Option<mpsc::UnboundedReceiver<FrameData>>>>,
+    // Publishes the active capture's receiver, or None while stopped
+    frame_receiver: watch::Sender<Option<FrameReceiver>>,
+    preferences: Arc<Mutex<PreferenceLearner>>,
+    profiles: Arc<Mutex<ProfileStore>>,
+    rules: Arc<Mutex<RuleStore>>,
+    window_tracker: Arc<dyn WindowTracker>,
+    ambient: SharedAmbientReading,
+    frame_source: Box<dyn FrameSource>,
+    // Frames are appended here while a recording is running
+    recorder: Arc<Mutex<Option<FrameRecorder>>>,
+    events: EventBus,
+    history: Arc<Mutex<HistoryStore>>,
+    exposure: Arc<Mutex<ExposureTracker>>,
+    sleep: Arc<Mutex<SleepJournal>>,
+    auth: Auth,
+}
+
+// Remembers what a per-application rule changed so it can be undone once
+// the application loses focus
+#[derive(Default)]
+struct RuleState {
+    suspended: bool,
+    profile_snapshot: Option<NightLightConfig>,
+    // The profile the rule switched to, so a later manual switch is left alone
+    rule_profile: Option<String>,
 }
 
 impl Default for NightLightConfig {
@@ -52,23 +170,66 @@ impl Default for NightLightConfig {
         Self {
             temperature: 4000,
             enabled: false,
+            adaptation_pause_secs: 900,
+            manual_override: None,
+            active_profile: None,
+            min_temperature: 3000,
+            max_temperature: 6500,
+            policy: TemperaturePolicy::Adaptive,
+            smoothing: 0.0,
+            freeze_on_fullscreen: true,
+            // Opt-in: scrolling through bright pages can trip it too
+            freeze_on_motion: false,
+            motion_threshold: 0.08,
+            capture: CaptureSettings::default(),
         }
     }
 }
 
+impl NightLightConfig {
+    fn active_override(&self, now: u64) -> Option<&ManualOverride> {
+        self.manual_override
+            .as_ref()
+            .filter(|manual_override| manual_override.expires_at > now)
+    }
+
+    fn apply_profile(&mut self, name: &str, profile: &Profile) {
+        self.active_profile = Some(name.to_string());
+        self.min_temperature = profile.min_temperature;
+        self.max_temperature = profile.max_temperature;
+        self.policy = profile.policy;
+        self.smoothing = profile.smoothing;
+        self.temperature = profile.temperature;
+    }
+
+    fn restore_profile_settings(&mut self, previous: &NightLightConfig) {
+        self.active_profile = previous.active_profile.clone();
+        self.min_temperature = previous.min_temperature;
+        self.max_temperature = previous.max_temperature;
+        self.policy = previous.policy;
+        self.smoothing = previous.smoothing;
+        self.temperature = previous.temperature;
+    }
+}
+
 // Copy the analysis functions from your main code
-fn analyze_frame_for_nightlight(frame: &FrameData) -> FrameAnalysis {
+fn analyze_frame_for_nightlight(frame: &FrameData, motion: &mut MotionDetector) -> FrameAnalysis {
     use pipewire::spa::param::video::VideoFormat;
     
     let brightness = calculate_average_brightness(frame);
     let blue_intensity = calculate_blue_intensity(frame);
     let ambient_level = estimate_ambient_light_level(frame);
+    let motion_level = motion.update(frame);
     
     FrameAnalysis {
         average_brightness: brightness,
         blue_intensity,
         ambient_light_level: ambient_level,
-        timestamp: frame.timestamp.elapsed().as_millis() as u64,
+        ambient_source: "screen-estimate",
+        ambient_lux: None,
+        motion_level,
+        // Wall-clock time the frame was captured
+        timestamp: unix_millis().saturating_sub(frame.timestamp.elapsed().as_millis() as u64),
         frame_size: frame.data.len(),
     }
 }
@@ -280,7 +441,7 @@ fn estimate_ambient_light_level(frame: &FrameData) -> f64 {
     ambient_estimate.min(1.0).max(0.0)
 }
 
-fn calculate_optimal_night_light_temperature(analysis: &FrameAnalysis) -> u32 {
+fn calculate_optimal_night_light_temperature(analysis: &FrameAnalysis, min_temperature: u32, max_temperature: u32) -> u32 {
     let brightness_factor = analysis.average_brightness / 255.0;
     let blue_factor = analysis.blue_intensity / 255.0;
     let ambient_factor = analysis.ambient_light_level;
@@ -291,161 +452,994 @@ fn calculate_optimal_night_light_temperature(analysis: &FrameAnalysis) -> u32 {
     
     let calculated_temp = base_temp_from_brightness + blue_adjustment + ambient_adjustment;
     
-    calculated_temp.max(3000.0).min(6500.0) as u32
+    calculated_temp.clamp(min_temperature as f64, max_temperature as f64) as u32
+}
+
+// Moves part of the way from the current temperature toward the target
+fn smooth_temperature(current: u32, target: u32, smoothing: f64) -> u32 {
+    let gap = target as f64 - current as f64;
+    let step = (gap * (1.0 - smoothing)).round();
+    // Rounding would otherwise stall the last few kelvin short of the target
+    let step = if step == 0.0 && gap != 0.0 { gap.signum() } else { step };
+    (current as f64 + step) as u32
+}
+
+// Every gsettings/gdbus call goes through here, so tests can see what the
+// backends would have done
+#[cfg(not(test))]
+fn desktop_command(program: &str, args: &[&str]) -> Result<std::process::Output, std::io::Error> {
+    std::process::Command::new(program).args(args).output()
+}
+
+#[cfg(test)]
+thread_local! {
+    static DESKTOP_CALLS: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+    static DESKTOP_EXIT_CODE: std::cell::Cell<i32> = const { std::cell::Cell::new(0) };
+}
+
+// Records the call instead of changing the desktop running the tests
+#[cfg(test)]
+fn desktop_command(program: &str, args: &[&str]) -> Result<std::process::Output, std::io::Error> {
+    use std::os::unix::process::ExitStatusExt;
+    DESKTOP_CALLS.with(|calls| calls.borrow_mut().push(format!("{} {}", program, args.join(" "))));
+    Ok(std::process::Output {
+        // Wait statuses keep the exit code in the second byte
+        status: std::process::ExitStatus::from_raw(DESKTOP_EXIT_CODE.get() << 8),
+        stdout: Vec::new(),
+        stderr: b"No such schema".to_vec(),
+    })
+}
+
+// gsettings and gdbus report most failures only through their exit status
+fn check_exit(program: &str, output: std::process::Output) -> Result<std::process::Output, std::io::Error> {
+    if output.status.success() {
+        return Ok(output);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(std::io::Error::other(format!("{} failed ({}): {}", program, output.status, stderr.trim())))
 }
 
 fn set_night_light_temperature(temperature: u32) -> Result<(), std::io::Error> {
-    Command::new("gsettings")
-        .args(&[
+    METRICS.time_backend(BackendCall::SetTemperature, || {
+        check_exit("gsettings", desktop_command("gsettings", &[
             "set",
             "org.gnome.settings-daemon.plugins.color",
             "night-light-temperature",
             &temperature.to_string()
-        ])
-        .output()?;
-    Ok(())
+        ])?)?;
+        Ok(())
+    })
+}
+
+fn read_schedule_hour(key: &str) -> Result<f64, std::io::Error> {
+    METRICS.time_backend(BackendCall::ReadSchedule, || {
+        let output = check_exit("gsettings", desktop_command("gsettings", &["get", "org.gnome.settings-daemon.plugins.color", key])?)?;
+
+        String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse::<f64>()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", key, e)))
+    })
+}
+
+// Milliseconds until the GNOME night light schedule next switches on or off
+fn millis_until_next_schedule_transition() -> Result<u64, std::io::Error> {
+    let from = read_schedule_hour("night-light-schedule-from")?;
+    let to = read_schedule_hour("night-light-schedule-to")?;
+    let hours = hours_until_local(from).min(hours_until_local(to));
+    Ok((hours * 3_600_000.0) as u64)
 }
 
 fn enable_night_light() -> Result<(), std::io::Error> {
-    Command::new("gsettings")
-        .args(&[
+    METRICS.time_backend(BackendCall::EnableNightLight, || {
+        check_exit("gsettings", desktop_command("gsettings", &[
             "set",
             "org.gnome.settings-daemon.plugins.color",
             "night-light-enabled",
             "true"
-        ])
-        .output()?;
+        ])?)?;
 
-    Command::new("gsettings")
-        .args(&[
+        check_exit("gsettings", desktop_command("gsettings", &[
             "set",
-            "org.gnome.settings-daemon.plugins.color", 
+            "org.gnome.settings-daemon.plugins.color",
             "night-light-schedule-automatic",
             "false"
-        ])
-        .output()?;
+        ])?)?;
 
-    Ok(())
+        Ok(())
+    })
+}
+
+fn set_screen_brightness(percent: u32) -> Result<(), std::io::Error> {
+    METRICS.time_backend(BackendCall::SetBrightness, || {
+        check_exit("gdbus", desktop_command("gdbus", &[
+            "call",
+            "--session",
+            "--dest", "org.gnome.SettingsDaemon.Power",
+            "--object-path", "/org/gnome/SettingsDaemon/Power",
+            "--method", "org.freedesktop.DBus.Properties.Set",
+            "org.gnome.SettingsDaemon.Power.Screen",
+            "Brightness",
+            &format!("<int32 {}>", percent),
+        ])?)?;
+        Ok(())
+    })
 }
 
 fn disable_night_light() -> Result<(), std::io::Error> {
-    Command::new("gsettings")
-        .args(&[
+    METRICS.time_backend(BackendCall::DisableNightLight, || {
+        check_exit("gsettings", desktop_command("gsettings", &[
             "set",
             "org.gnome.settings-daemon.plugins.color",
-            "night-light-enabled", 
+            "night-light-enabled",
             "false"
-        ])
-        .output()?;
-    Ok(())
+        ])?)?;
+        Ok(())
+    })
 }
 
 // API Handlers
 
+#[utoipa::path(
+    get,
+    path = "/status",
+    tag = "monitoring",
+    summary = "System status",
+    responses(
+        (status = 200, description = "Current status", body = SystemStatus),
+    )
+)]
 async fn get_status(data: web::Data<AppState>) -> Result<HttpResponse> {
-    let status = data.status.lock().unwrap().clone(); // Now works with Clone trait
-    Ok(HttpResponse::Ok().json(status))
+    Ok(HttpResponse::Ok().json(status_snapshot(&data)))
 }
 
+fn status_snapshot(data: &AppState) -> SystemStatus {
+    let mut status = data.status.lock().unwrap().clone(); // Now works with Clone trait
+    let now = unix_millis();
+    status.override_remaining_secs = data.config.lock().unwrap()
+        .active_override(now)
+        .map(|manual_override| (manual_override.expires_at - now) / 1000);
+    if status.running {
+        status.capture_stream = data.frame_source.metadata();
+    }
+    status
+}
+
+#[utoipa::path(
+    get,
+    path = "/config",
+    tag = "config",
+    summary = "Current configuration",
+    responses(
+        (status = 200, description = "Current configuration", body = NightLightConfig),
+    )
+)]
 async fn get_config(data: web::Data<AppState>) -> Result<HttpResponse> {
-    let config = data.config.lock().unwrap().clone();
-    Ok(HttpResponse::Ok().json(config))
+    let mut config = data.config.lock().unwrap();
+    expire_override(&data, &mut config);
+    Ok(HttpResponse::Ok().json(config.clone()))
+}
+
+// Drops a hold whose time is up and announces it. The controller does this
+// every step, but it doesn't run while monitoring is stopped.
+fn expire_override(data: &AppState, config: &mut NightLightConfig) {
+    if config.manual_override.is_some() && config.active_override(unix_millis()).is_none() {
+        config.manual_override = None;
+        println!("Manual override expired, resuming adaptive adjustment");
+        data.events.publish(Event::ConfigChanged { config: config.clone() });
+    }
 }
 
+#[utoipa::path(
+    put,
+    path = "/config",
+    tag = "config",
+    summary = "Update configuration",
+    request_body = UpdateConfigRequest,
+    responses(
+        (status = 200, description = "The updated configuration", body = NightLightConfig),
+        (status = 400, description = "Invalid or missing fields", body = ErrorBody),
+        (status = 500, description = "The desktop settings could not be changed", body = ErrorBody),
+    )
+)]
 async fn update_config(
     data: web::Data<AppState>,
     req: web::Json<UpdateConfigRequest>
-) -> Result<HttpResponse> {
+) -> Result<HttpResponse, ApiError> {
+    let config = apply_config_update(&data, &req)?;
+    Ok(HttpResponse::Ok().json(config))
+}
+
+// Shared by PUT /config and the WebSocket update_config command
+fn apply_config_update(data: &AppState, req: &UpdateConfigRequest) -> Result<NightLightConfig, ApiError> {
+    // Every field is checked before any is applied, so a rejected request
+    // leaves the config untouched
+    if let Some(threshold) = req.motion_threshold
+        && !(0.0..=1.0).contains(&threshold)
+    {
+        return Err(ApiError::invalid("motion_threshold", "Motion threshold must be between 0.0 and 1.0"));
+    }
+    if let Some(capture) = &req.capture {
+        capture.validate().map_err(|e| e.within("capture"))?;
+    }
+    if let Some(temperature) = req.temperature
+        && !(1000..=10000).contains(&temperature)
+    {
+        return Err(ApiError::invalid("temperature", "Temperature must be between 1000K and 10000K"));
+    }
+
+    let updated = req.temperature.is_some()
+        || req.enabled.is_some()
+        || req.adaptation_pause_secs.is_some()
+        || req.freeze_on_fullscreen.is_some()
+        || req.freeze_on_motion.is_some()
+        || req.motion_threshold.is_some()
+        || req.capture.is_some();
+    if !updated {
+        return Err(ApiError::NoChanges);
+    }
+
+    let (running, analysis) = {
+        let status = data.status.lock().unwrap();
+        (status.running, status.current_analysis.clone())
+    };
     let mut config = data.config.lock().unwrap();
-    let mut updated = false;
+    expire_override(data, &mut config);
+
+    if let Some(pause_secs) = req.adaptation_pause_secs {
+        config.adaptation_pause_secs = pause_secs;
+    }
+    if let Some(freeze) = req.freeze_on_fullscreen {
+        config.freeze_on_fullscreen = freeze;
+    }
+    if let Some(freeze) = req.freeze_on_motion {
+        config.freeze_on_motion = freeze;
+    }
+    if let Some(threshold) = req.motion_threshold {
+        config.motion_threshold = threshold;
+    }
+    if let Some(capture) = &req.capture {
+        config.capture = capture.clone();
+    }
+
+    // The config keeps the requested values even if the desktop refuses them,
+    // matching what a later enable or restart would apply
+    let mut result = Ok(());
 
     if let Some(temperature) = req.temperature {
-        if temperature >= 1000 && temperature <= 10000 {
-            config.temperature = temperature;
-            if config.enabled {
-                if let Err(e) = set_night_light_temperature(temperature) {
-                    return Ok(HttpResponse::InternalServerError().json(format!("Failed to set temperature: {}", e)));
-                }
+        // A manual change during adaptation is feedback for the learned policy
+        if running && config.enabled {
+            let mut preferences = data.preferences.lock().unwrap();
+            if let Some(analysis) = analysis {
+                let baseline = calculate_optimal_night_light_temperature(&analysis, config.min_temperature, config.max_temperature);
+                preferences.record_override(&analysis, local_hour_of_day(), baseline, temperature);
+            }
+            preferences.pause(Duration::from_secs(config.adaptation_pause_secs));
+        }
+        config.temperature = temperature;
+        // A hold keeps the temperature it was last given
+        if let Some(manual_override) = config.manual_override.as_mut() {
+            manual_override.temperature = temperature;
+        }
+        if config.enabled {
+            result = set_night_light_temperature(temperature).map_err(|e| ApiError::backend("set temperature", e));
+            if result.is_ok() {
+                data.events.publish(Event::TemperatureApplied { temperature });
             }
-            updated = true;
-        } else {
-            return Ok(HttpResponse::BadRequest().json("Temperature must be between 1000K and 10000K"));
         }
     }
 
     if let Some(enabled) = req.enabled {
         config.enabled = enabled;
-        let result = if enabled {
-            enable_night_light().and_then(|_| set_night_light_temperature(config.temperature))
-        } else {
-            disable_night_light()
-        };
-
-        if let Err(e) = result {
-            return Ok(HttpResponse::InternalServerError().json(format!("Failed to change night light state: {}", e)));
+        if result.is_ok() {
+            result = if enabled {
+                enable_night_light().and_then(|_| set_night_light_temperature(config.temperature))
+            } else {
+                disable_night_light()
+            }
+            .map_err(|e| ApiError::backend("change night light state", e));
         }
-        updated = true;
     }
 
-    if updated {
-        Ok(HttpResponse::Ok().json(config.clone()))
-    } else {
-        Ok(HttpResponse::BadRequest().json("No valid parameters provided"))
-    }
+    data.events.publish(Event::ConfigChanged { config: config.clone() });
+    result.map(|_| config.clone())
 }
 
-async fn start_monitoring(data: web::Data<AppState>) -> Result<HttpResponse> {
-    let mut status = data.status.lock().unwrap();
-    
-    if status.running {
-        return Ok(HttpResponse::BadRequest().json("Monitoring is already running"));
+#[utoipa::path(
+    post,
+    path = "/start",
+    tag = "monitoring",
+    summary = "Start monitoring",
+    responses(
+        (status = 200, description = "Monitoring started", body = String),
+        (status = 409, description = "Already running", body = ErrorBody),
+        (status = 500, description = "Capture could not be started", body = ErrorBody),
+    )
+)]
+async fn start_monitoring(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    if data.status.lock().unwrap().running {
+        return Err(ApiError::AlreadyRunning);
     }
 
-    // Start screen capture
-    match start_screen_capture().await {
+    let settings = data.config.lock().unwrap().capture.clone();
+
+    // Start capture; the portal dialog can take a while, so no lock is held
+    match data.frame_source.start(&settings).await {
         Ok(receiver) => {
+            let mut status = data.status.lock().unwrap();
+            if status.running {
+                return Err(ApiError::AlreadyRunning);
+            }
             status.running = true;
             status.last_update = std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_millis() as u64;
 
-            // Store the receiver in app state
-            let mut frame_receiver = data.frame_receiver.lock().unwrap();
-            *frame_receiver = Some(receiver);
+            // Hand the receiver to the frame processor
+            data.frame_receiver.send_replace(Some(receiver));
+            data.events.publish(Event::MonitoringStarted { source: data.frame_source.name() });
 
             Ok(HttpResponse::Ok().json("Screen monitoring started"))
         },
-        Err(e) => {
-            Ok(HttpResponse::InternalServerError().json(format!("Failed to start monitoring: {}", e)))
-        }
+        Err(e) => Err(ApiError::CaptureFailed { message: e.to_string() }),
     }
 }
 
-async fn stop_monitoring(data: web::Data<AppState>) -> Result<HttpResponse> {
+#[utoipa::path(
+    post,
+    path = "/stop",
+    tag = "monitoring",
+    summary = "Stop monitoring",
+    responses(
+        (status = 200, description = "Monitoring stopped", body = String),
+        (status = 409, description = "Not running", body = ErrorBody),
+        (status = 500, description = "Night light could not be disabled", body = ErrorBody),
+    )
+)]
+async fn stop_monitoring(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
     let mut status = data.status.lock().unwrap();
     
     if !status.running {
-        return Ok(HttpResponse::BadRequest().json("Monitoring is not running"));
+        return Err(ApiError::NotRunning);
     }
 
     status.running = false;
     
-    // Remove the receiver
-    let mut frame_receiver = data.frame_receiver.lock().unwrap();
-    *frame_receiver = None;
+    // Dropping the receiver ends the capture stream
+    data.frame_receiver.send_replace(None);
+    data.frame_source.stop();
+    data.events.publish(Event::MonitoringStopped);
 
     // Disable night light
-    if let Err(e) = disable_night_light() {
-        return Ok(HttpResponse::InternalServerError().json(format!("Failed to disable night light: {}", e)));
-    }
+    disable_night_light().map_err(|e| ApiError::backend("disable night light", e))?;
 
     let mut config = data.config.lock().unwrap();
     config.enabled = false;
+    data.events.publish(Event::ConfigChanged { config: config.clone() });
 
     Ok(HttpResponse::Ok().json("Screen monitoring stopped"))
 }
 
+// Recordings only ever go into config_dir/recordings, whatever the client asks for
+fn recording_path(name: Option<&str>) -> Result<std::path::PathBuf, ApiError> {
+    let name = match name {
+        Some(name) => {
+            if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+                return Err(ApiError::invalid("name", "name must be a plain file name"));
+            }
+            name.to_string()
+        },
+        None => format!("{}.lrec", unix_millis()),
+    };
+    Ok(storage::config_dir().join("recordings").join(name))
+}
+
+#[utoipa::path(
+    post,
+    path = "/recording",
+    tag = "recording",
+    summary = "Start recording captured frames",
+    request_body = Option<RecordingRequest>,
+    responses(
+        (status = 200, description = "Recording started", body = RecordingStarted),
+        (status = 400, description = "The name is not a plain file name", body = ErrorBody),
+        (status = 409, description = "A recording is already running", body = ErrorBody),
+        (status = 500, description = "The recording file could not be created", body = ErrorBody),
+    )
+)]
+async fn start_recording(
+    data: web::Data<AppState>,
+    req: Option<web::Json<RecordingRequest>>
+) -> Result<HttpResponse, ApiError> {
+    let mut recorder = data.recorder.lock().unwrap();
+    if recorder.is_some() {
+        return Err(ApiError::RecordingInProgress);
+    }
+
+    let name = req.and_then(|req| req.into_inner().name);
+    let path = recording_path(name.as_deref())?;
+    let created = FrameRecorder::create(path).map_err(|e| ApiError::storage("start recording", e))?;
+    let path = created.path().to_path_buf();
+    *recorder = Some(created);
+    Ok(HttpResponse::Ok().json(RecordingStarted { path }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/recording",
+    tag = "recording",
+    summary = "Finish the recording",
+    responses(
+        (status = 200, description = "The finished recording", body = recording::RecordingSummary),
+        (status = 409, description = "No recording is running", body = ErrorBody),
+        (status = 500, description = "The recording could not be finished", body = ErrorBody),
+    )
+)]
+async fn stop_recording(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let Some(recorder) = data.recorder.lock().unwrap().take() else {
+        return Err(ApiError::NoRecording);
+    };
+
+    let summary = recorder.finish().map_err(|e| ApiError::storage("finish recording", e))?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+#[utoipa::path(
+    post,
+    path = "/override",
+    tag = "config",
+    summary = "Hold a temperature for a while",
+    request_body = OverrideRequest,
+    responses(
+        (status = 200, description = "The updated configuration", body = NightLightConfig),
+        (status = 400, description = "Invalid temperature or duration", body = ErrorBody),
+        (status = 500, description = "The desktop settings could not be changed", body = ErrorBody),
+    )
+)]
+async fn set_override(
+    data: web::Data<AppState>,
+    req: web::Json<OverrideRequest>
+) -> Result<HttpResponse, ApiError> {
+    if req.temperature < 1000 || req.temperature > 10000 {
+        return Err(ApiError::invalid("temperature", "Temperature must be between 1000K and 10000K"));
+    }
+
+    let until_next_transition = req.until_next_transition.unwrap_or(false);
+    let duration_ms = match (req.duration_minutes, until_next_transition) {
+        (Some(minutes), false) if minutes > 0 => minutes * 60_000,
+        (None, true) => millis_until_next_schedule_transition()
+            .map_err(|e| ApiError::backend("read night light schedule", e))?,
+        _ => {
+            return Err(ApiError::invalid(
+                "duration_minutes",
+                "Provide either a positive duration_minutes or until_next_transition",
+            ));
+        }
+    };
+
+    let mut config = data.config.lock().unwrap();
+    if config.enabled {
+        set_night_light_temperature(req.temperature).map_err(|e| ApiError::backend("set temperature", e))?;
+    }
+
+    config.temperature = req.temperature;
+    config.manual_override = Some(ManualOverride {
+        temperature: req.temperature,
+        expires_at: unix_millis() + duration_ms,
+        until_next_transition,
+    });
+    if config.enabled {
+        data.events.publish(Event::TemperatureApplied { temperature: req.temperature });
+    }
+    data.events.publish(Event::ConfigChanged { config: config.clone() });
+
+    Ok(HttpResponse::Ok().json(config.clone()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/override",
+    tag = "config",
+    summary = "Cancel the held temperature",
+    responses(
+        (status = 200, description = "The updated configuration", body = NightLightConfig),
+    )
+)]
+async fn clear_override(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let mut config = data.config.lock().unwrap();
+    config.manual_override = None;
+    data.events.publish(Event::ConfigChanged { config: config.clone() });
+    Ok(HttpResponse::Ok().json(config.clone()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/profiles",
+    tag = "profiles",
+    summary = "List profiles",
+    responses(
+        (status = 200, description = "Every profile", body = Vec<profiles::NamedProfile>),
+    )
+)]
+async fn list_profiles(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let profiles = data.profiles.lock().unwrap().list();
+    Ok(HttpResponse::Ok().json(profiles))
+}
+
+#[utoipa::path(
+    get,
+    path = "/profiles/{name}",
+    tag = "profiles",
+    summary = "Show a profile",
+    params(("name" = String, Path, description = "Profile name")),
+    responses(
+        (status = 200, description = "The profile", body = profiles::NamedProfile),
+        (status = 404, description = "No such profile", body = ErrorBody),
+    )
+)]
+async fn get_profile(data: web::Data<AppState>, name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    match data.profiles.lock().unwrap().get(&name) {
+        Some(profile) => Ok(HttpResponse::Ok().json(profile)),
+        None => Err(ApiError::ProfileNotFound { name: name.into_inner() }),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/profiles/{name}",
+    tag = "profiles",
+    summary = "Create or replace a profile",
+    params(("name" = String, Path, description = "Profile name")),
+    request_body = Profile,
+    responses(
+        (status = 200, description = "The replaced profile", body = profiles::NamedProfile),
+        (status = 201, description = "The new profile", body = profiles::NamedProfile),
+        (status = 400, description = "Invalid profile", body = ErrorBody),
+        (status = 500, description = "The profile could not be saved or applied", body = ErrorBody),
+    )
+)]
+async fn put_profile(
+    data: web::Data<AppState>,
+    name: web::Path<String>,
+    req: web::Json<Profile>
+) -> Result<HttpResponse, ApiError> {
+    let name = name.into_inner();
+    if name.trim().is_empty() || name.len() > 64 {
+        return Err(ApiError::invalid("name", "Profile name must be between 1 and 64 characters"));
+    }
+    req.validate()?;
+
+    let profile = req.into_inner();
+    let mut profiles = data.profiles.lock().unwrap();
+    let created = profiles
+        .upsert(&name, profile.clone())
+        .map_err(|e| ApiError::storage("save profile", e))?;
+
+    // Edits to the profile in use take effect immediately
+    let mut config = data.config.lock().unwrap();
+    if config.active_profile.as_deref() == Some(name.as_str()) {
+        // The settings change even if the desktop refuses them
+        let applied = activate_profile_settings(&mut config, &name, &profile);
+        data.events.publish(Event::ConfigChanged { config: config.clone() });
+        applied.map_err(|e| ApiError::backend("apply profile", e))?;
+    }
+
+    let stored = profiles.get(&name);
+    if created {
+        Ok(HttpResponse::Created().json(stored))
+    } else {
+        Ok(HttpResponse::Ok().json(stored))
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/profiles/{name}",
+    tag = "profiles",
+    summary = "Delete a profile",
+    params(("name" = String, Path, description = "Profile name")),
+    responses(
+        (status = 200, description = "Profile deleted", body = String),
+        (status = 404, description = "No such profile", body = ErrorBody),
+        (status = 500, description = "Profiles could not be saved", body = ErrorBody),
+    )
+)]
+async fn delete_profile(data: web::Data<AppState>, name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let mut profiles = data.profiles.lock().unwrap();
+    match profiles.remove(&name) {
+        Ok(true) => {
+            let mut config = data.config.lock().unwrap();
+            if config.active_profile.as_deref() == Some(name.as_str()) {
+                config.active_profile = None;
+                data.events.publish(Event::ConfigChanged { config: config.clone() });
+            }
+            Ok(HttpResponse::Ok().json(format!("Profile '{}' deleted", name)))
+        },
+        Ok(false) => Err(ApiError::ProfileNotFound { name: name.into_inner() }),
+        Err(e) => Err(ApiError::storage("save profiles", e)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/profiles/{name}/activate",
+    tag = "profiles",
+    summary = "Switch to a profile",
+    params(("name" = String, Path, description = "Profile name")),
+    responses(
+        (status = 200, description = "The updated configuration", body = NightLightConfig),
+        (status = 404, description = "No such profile", body = ErrorBody),
+        (status = 500, description = "The profile could not be applied or saved", body = ErrorBody),
+    )
+)]
+async fn activate_profile(data: web::Data<AppState>, name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let mut profiles = data.profiles.lock().unwrap();
+    let Some(named) = profiles.get(&name) else {
+        return Err(ApiError::ProfileNotFound { name: name.into_inner() });
+    };
+
+    let mut config = data.config.lock().unwrap();
+    // Explicitly picking a profile ends any temperature hold
+    config.manual_override = None;
+    let applied = activate_profile_settings(&mut config, &named.name, &named.profile);
+    data.events.publish(Event::ConfigChanged { config: config.clone() });
+    applied.map_err(|e| ApiError::backend("apply profile", e))?;
+    profiles.set_active(&named.name).map_err(|e| ApiError::storage("save profiles", e))?;
+
+    Ok(HttpResponse::Ok().json(config.clone()))
+}
+
+fn activate_profile_settings(
+    config: &mut NightLightConfig,
+    name: &str,
+    profile: &Profile,
+) -> Result<(), std::io::Error> {
+    config.apply_profile(name, profile);
+    if config.enabled {
+        set_night_light_temperature(config.temperature)?;
+    }
+    if let Some(brightness) = profile.brightness {
+        set_screen_brightness(brightness)?;
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/rules",
+    tag = "rules",
+    summary = "List per-application rules",
+    responses(
+        (status = 200, description = "Every rule", body = Vec<rules::AppRule>),
+    )
+)]
+async fn list_rules(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let rules = data.rules.lock().unwrap().list();
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+#[utoipa::path(
+    put,
+    path = "/rules/{app}",
+    tag = "rules",
+    summary = "Create or replace a rule",
+    params(("app" = String, Path, description = "Application id, as reported in focused_app")),
+    request_body = RuleAction,
+    responses(
+        (status = 200, description = "Every rule, after replacing this one", body = Vec<rules::AppRule>),
+        (status = 201, description = "Every rule, after adding this one", body = Vec<rules::AppRule>),
+        (status = 400, description = "Invalid rule", body = ErrorBody),
+        (status = 500, description = "Rules could not be saved", body = ErrorBody),
+    )
+)]
+async fn put_rule(
+    data: web::Data<AppState>,
+    app: web::Path<String>,
+    req: web::Json<RuleAction>
+) -> Result<HttpResponse, ApiError> {
+    let app = app.into_inner();
+    if app.trim().is_empty() {
+        return Err(ApiError::invalid("app", "Application name must not be empty"));
+    }
+    req.validate()?;
+    if let RuleAction::SwitchProfile { profile } = &*req
+        && data.profiles.lock().unwrap().get(profile).is_none()
+    {
+        return Err(ApiError::invalid("profile", format!("Profile '{}' not found", profile)));
+    }
+
+    let mut rules = data.rules.lock().unwrap();
+    match rules.upsert(&app, req.into_inner()) {
+        Ok(true) => Ok(HttpResponse::Created().json(rules.list())),
+        Ok(false) => Ok(HttpResponse::Ok().json(rules.list())),
+        Err(e) => Err(ApiError::storage("save rules", e)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/rules/{app}",
+    tag = "rules",
+    summary = "Delete a rule",
+    params(("app" = String, Path, description = "Application id, as reported in focused_app")),
+    responses(
+        (status = 200, description = "The remaining rules", body = Vec<rules::AppRule>),
+        (status = 404, description = "No rule for this application", body = ErrorBody),
+        (status = 500, description = "Rules could not be saved", body = ErrorBody),
+    )
+)]
+async fn delete_rule(data: web::Data<AppState>, app: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let mut rules = data.rules.lock().unwrap();
+    match rules.remove(&app) {
+        Ok(true) => Ok(HttpResponse::Ok().json(rules.list())),
+        Ok(false) => Err(ApiError::RuleNotFound { app: app.into_inner() }),
+        Err(e) => Err(ApiError::storage("save rules", e)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/preferences",
+    tag = "preferences",
+    summary = "Learned preference model",
+    responses(
+        (status = 200, description = "The model and its samples", body = preferences::PreferenceSnapshot),
+    )
+)]
+async fn get_preferences(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let snapshot = data.preferences.lock().unwrap().snapshot();
+    Ok(HttpResponse::Ok().json(snapshot))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/preferences",
+    tag = "preferences",
+    summary = "Reset learned preferences",
+    responses(
+        (status = 200, description = "The emptied model", body = preferences::PreferenceSnapshot),
+    )
+)]
+async fn reset_preferences(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let mut preferences = data.preferences.lock().unwrap();
+    preferences.reset();
+    Ok(HttpResponse::Ok().json(preferences.snapshot()))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+    resolution: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct HistoryResponse {
+    from: u64,
+    to: u64,
+    resolution_ms: u64,
+    points: Vec<history::HistoryPoint>,
+}
+
+// "90", "90s", "5m", "1h" or "1d", in milliseconds
+fn parse_resolution(resolution: &str) -> Option<u64> {
+    let resolution = resolution.trim();
+    let (number, unit_ms) = match resolution.char_indices().last()? {
+        (at, 's') => (&resolution[..at], 1000),
+        (at, 'm') => (&resolution[..at], 60_000),
+        (at, 'h') => (&resolution[..at], 3_600_000),
+        (at, 'd') => (&resolution[..at], 86_400_000),
+        _ => (resolution, 1000),
+    };
+    number.parse::<u64>().ok().filter(|n| *n > 0)?.checked_mul(unit_ms)
+}
+
+// GET /history: aggregated series between `from` and `to` (Unix ms, the last
+// 24 hours by default) in buckets of `resolution`
+#[utoipa::path(
+    get,
+    path = "/history",
+    tag = "history",
+    summary = "Analysis and temperature history",
+    params(HistoryQuery),
+    responses(
+        (status = 200, description = "Aggregated series", body = HistoryResponse),
+        (status = 400, description = "Invalid range or resolution", body = ErrorBody),
+    )
+)]
+async fn get_history(data: web::Data<AppState>, query: web::Query<HistoryQuery>) -> Result<HttpResponse, ApiError> {
+    let to = query.to.unwrap_or_else(unix_millis);
+    let from = query.from.unwrap_or(to.saturating_sub(24 * 3_600_000));
+    if from >= to {
+        return Err(ApiError::invalid("from", "from must be before to"));
+    }
+
+    let span = to - from;
+    let resolution = match query.resolution.as_deref() {
+        Some(resolution) => match parse_resolution(resolution) {
+            Some(resolution) => resolution,
+            None => return Err(ApiError::invalid("resolution", "Invalid resolution (use e.g. 30s, 5m, 1h or 1d)")),
+        },
+        // Enough points for a chart, rounded up to whole seconds
+        None => (span / 500).div_ceil(1000).max(1) * 1000,
+    };
+    if span.div_ceil(resolution) > history::MAX_POINTS {
+        return Err(ApiError::invalid(
+            "resolution",
+            format!("Resolution too fine for this range (at most {} points)", history::MAX_POINTS),
+        ));
+    }
+
+    let points = data.history.lock().unwrap().query(from, to, resolution);
+    Ok(HttpResponse::Ok().json(HistoryResponse { from, to, resolution_ms: resolution, points }))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ReportQuery {
+    // The day to report on, or the last day of the week; today by default
+    date: Option<String>,
+    // json (the default) or csv
+    format: Option<String>,
+}
+
+impl ReportQuery {
+    // None for formats we can't produce
+    fn wants_csv(&self) -> Option<bool> {
+        match self.format.as_deref() {
+            None | Some("json") => Some(false),
+            Some("csv") => Some(true),
+            Some(_) => None,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/daily",
+    tag = "reports",
+    summary = "Blue-light exposure for a day",
+    params(ReportQuery),
+    responses(
+        (status = 200, description = "The day's exposure; CSV when format=csv", body = exposure::DayExposure),
+        (status = 400, description = "Invalid date or format", body = ErrorBody),
+    )
+)]
+async fn get_daily_report(data: web::Data<AppState>, query: web::Query<ReportQuery>) -> Result<HttpResponse, ApiError> {
+    let Some(csv) = query.wants_csv() else {
+        return Err(ApiError::invalid("format", "format must be json or csv"));
+    };
+    let Some(day) = data.exposure.lock().unwrap().daily(query.date.as_deref()) else {
+        return Err(ApiError::invalid("date", "date must be YYYY-MM-DD"));
+    };
+
+    if csv {
+        return Ok(HttpResponse::Ok().content_type("text/csv").body(exposure::daily_csv(&day)));
+    }
+    Ok(HttpResponse::Ok().json(day))
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/weekly",
+    tag = "reports",
+    summary = "Blue-light exposure for the seven days ending on date",
+    params(ReportQuery),
+    responses(
+        (status = 200, description = "The week's exposure; CSV when format=csv", body = exposure::WeeklyReport),
+        (status = 400, description = "Invalid date or format", body = ErrorBody),
+    )
+)]
+async fn get_weekly_report(data: web::Data<AppState>, query: web::Query<ReportQuery>) -> Result<HttpResponse, ApiError> {
+    let Some(csv) = query.wants_csv() else {
+        return Err(ApiError::invalid("format", "format must be json or csv"));
+    };
+    let Some(report) = data.exposure.lock().unwrap().weekly(query.date.as_deref()) else {
+        return Err(ApiError::invalid("date", "date must be YYYY-MM-DD"));
+    };
+
+    if csv {
+        return Ok(HttpResponse::Ok().content_type("text/csv").body(exposure::weekly_csv(&report)));
+    }
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct LogSleepRequest {
+    latency_minutes: f64,
+    // The evening the night followed; last night by default
+    date: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/sleep",
+    tag = "sleep",
+    summary = "Logged sleep latencies",
+    responses(
+        (status = 200, description = "Every logged night", body = Vec<sleep::SleepLog>),
+    )
+)]
+async fn list_sleep(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let sleep = data.sleep.lock().unwrap();
+    Ok(HttpResponse::Ok().json(sleep.logs()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/sleep",
+    tag = "sleep",
+    summary = "Log a night's sleep latency",
+    request_body = LogSleepRequest,
+    responses(
+        (status = 200, description = "The stored entry", body = sleep::SleepLog),
+        (status = 400, description = "Invalid latency or date", body = ErrorBody),
+    )
+)]
+async fn log_sleep(data: web::Data<AppState>, req: web::Json<LogSleepRequest>) -> Result<HttpResponse, ApiError> {
+    if !req.latency_minutes.is_finite() || !(0.0..=600.0).contains(&req.latency_minutes) {
+        return Err(ApiError::invalid("latency_minutes", "latency_minutes must be between 0 and 600"));
+    }
+
+    let date = match &req.date {
+        Some(date) if clock::local_noon_millis(date).is_none() => {
+            return Err(ApiError::invalid("date", "date must be YYYY-MM-DD"));
+        },
+        Some(date) => date.clone(),
+        // Logged the morning after, so half a day back lands on last evening
+        None => exposure::exposure_date(unix_millis().saturating_sub(12 * 3_600_000)),
+    };
+
+    let entry = data.sleep.lock().unwrap().log(date, req.latency_minutes);
+    Ok(HttpResponse::Ok().json(entry))
+}
+
+#[utoipa::path(
+    get,
+    path = "/insights/sleep",
+    tag = "sleep",
+    summary = "Sleep-latency model and its cross-validated accuracy",
+    responses(
+        (status = 200, description = "The fitted model, once enough nights are logged", body = sleep::SleepInsights),
+    )
+)]
+async fn get_sleep_insights(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let logs = data.sleep.lock().unwrap().logs().to_vec();
+    let exposure = data.exposure.lock().unwrap();
+    let today = exposure::exposure_date(unix_millis());
+    Ok(HttpResponse::Ok().json(sleep::insights(&logs, &exposure, &today)))
+}
+
+// GET /metrics: Prometheus text format
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "system",
+    summary = "Prometheus metrics",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format, version 0.0.4", body = String, content_type = "text/plain"),
+    )
+)]
+async fn get_metrics(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let status = status_snapshot(&data);
+    let temperature = data.config.lock().unwrap().temperature;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(METRICS.render(&status, temperature)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "system",
+    summary = "Health check",
+    security(()),
+    responses(
+        (status = 200, description = "Service is up", body = Object),
+    )
+)]
 async fn get_health() -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "status": "healthy",
@@ -456,73 +1450,440 @@ async fn get_health() -> Result<HttpResponse> {
     })))
 }
 
-// Background task to process frames
+// GET /: the bundled UI. It reads the token from the URL fragment, which
+// browsers never send to the server.
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "system",
+    summary = "Web UI",
+    security(()),
+    responses(
+        (status = 200, description = "The bundled UI; open it as /#token=<API token>", body = String, content_type = "text/html"),
+    )
+)]
+async fn get_ui() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("../index.html")))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct TicketResponse {
+    ticket: String,
+    expires_in_secs: u64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/auth/ticket",
+    tag = "system",
+    summary = "Short-lived ticket for /ws and /events",
+    description = "Browsers can't send an Authorization header when opening a WebSocket or EventSource, \
+        so they pass ?ticket=... instead. A ticket opens only the path it is first used on, \
+        and keeps letting reconnects to that path through until it expires.",
+    responses(
+        (status = 200, description = "A new ticket", body = TicketResponse),
+        (status = 401, description = "Missing or invalid API token", body = ErrorBody),
+    )
+)]
+async fn create_ticket(data: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let ticket = data.auth.issue_ticket().map_err(|e| ApiError::storage("issue ticket", e))?;
+    Ok(HttpResponse::Ok().json(TicketResponse { ticket, expires_in_secs: auth::TICKET_TTL_MS / 1000 }))
+}
+
+// Applies or reverts the side effects of the focused application's rule
+fn apply_app_rule(app_state: &AppState, rule_state: &mut RuleState, rule: Option<&RuleAction>) {
+    // Look the profile up before taking the config lock, matching the handlers' lock order
+    let rule_profile = match rule {
+        Some(RuleAction::SwitchProfile { profile }) => app_state.profiles.lock().unwrap().get(profile),
+        _ => None,
+    };
+
+    let mut config = app_state.config.lock().unwrap();
+
+    let disable = matches!(rule, Some(RuleAction::Disable));
+    if disable && !rule_state.suspended && config.enabled {
+        match disable_night_light() {
+            Ok(()) => rule_state.suspended = true,
+            Err(e) => app_state.events.backend_error(format!("Failed to disable night light for focused application: {}", e)),
+        }
+    } else if !disable && rule_state.suspended {
+        rule_state.suspended = false;
+        if config.enabled
+            && let Err(e) = enable_night_light().and_then(|_| set_night_light_temperature(config.temperature))
+        {
+            app_state.events.backend_error(format!("Failed to re-enable night light: {}", e));
+        }
+    }
+
+    match rule_profile {
+        Some(named) => {
+            if config.active_profile.as_deref() != Some(named.name.as_str()) {
+                // Moving between two rules' profiles keeps the original snapshot,
+                // but a profile the user picked meanwhile becomes the one to restore
+                if rule_state.profile_snapshot.is_none() || config.active_profile != rule_state.rule_profile {
+                    rule_state.profile_snapshot = Some(config.clone());
+                }
+                rule_state.rule_profile = Some(named.name.clone());
+                let applied = activate_profile_settings(&mut config, &named.name, &named.profile);
+                app_state.events.publish(Event::ConfigChanged { config: config.clone() });
+                if let Err(e) = applied {
+                    app_state.events.backend_error(format!("Failed to switch to profile '{}': {}", named.name, e));
+                }
+            }
+        },
+        None => {
+            let rule_profile = rule_state.rule_profile.take();
+            // If the user switched profiles while the rule applied, theirs stays
+            if let Some(previous) = rule_state.profile_snapshot.take()
+                && config.active_profile == rule_profile
+            {
+                config.restore_profile_settings(&previous);
+                app_state.events.publish(Event::ConfigChanged { config: config.clone() });
+                if config.enabled
+                    && !rule_state.suspended
+                    && let Err(e) = set_night_light_temperature(config.temperature)
+                {
+                    app_state.events.backend_error(format!("Failed to restore temperature: {}", e));
+                }
+            }
+        },
+    }
+}
+
+// Runs the per-application rules and the temperature policy against the
+// newest analysis
+fn control_step(app_state: &AppState, rule_state: &mut RuleState, analysis: &FrameAnalysis) {
+    // Per-application rules run before the temperature policy
+    let focused = app_state.window_tracker.focused_window();
+    let rule = focused
+        .as_ref()
+        .and_then(|window| app_state.rules.lock().unwrap().rule_for(&window.app_id).cloned());
+    let fullscreen = focused.as_ref().is_some_and(|window| window.fullscreen);
+    apply_app_rule(app_state, rule_state, rule.as_ref());
+
+    let mut config = app_state.config.lock().unwrap();
+
+    // Hold still during films and games so the colour doesn't pump
+    let high_motion = analysis.motion_level >= config.motion_threshold;
+    let frozen = (config.freeze_on_fullscreen && fullscreen)
+        || (config.freeze_on_motion && high_motion);
+    {
+        let mut status = app_state.status.lock().unwrap();
+        status.focused_app = focused.map(|window| window.app_id);
+        status.fullscreen = fullscreen;
+        status.high_motion = high_motion;
+        status.adjustments_frozen = frozen;
+    }
+
+    expire_override(app_state, &mut config);
+    let held = config.manual_override.is_some();
+    let preferences = app_state.preferences.lock().unwrap();
+    let adaptive = config.policy == TemperaturePolicy::Adaptive;
+    if config.enabled && adaptive && !held && !frozen && !rule_state.suspended && !preferences.is_paused() {
+        let baseline = calculate_optimal_night_light_temperature(analysis, config.min_temperature, config.max_temperature);
+        let mut target = preferences
+            .personalize(analysis, local_hour_of_day(), baseline)
+            .clamp(config.min_temperature, config.max_temperature);
+        if let Some(RuleAction::CapTemperature { max_temperature }) = rule {
+            target = target.min(max_temperature);
+        }
+        let optimal_temperature = smooth_temperature(config.temperature, target, config.smoothing);
+        drop(preferences);
+        if optimal_temperature != config.temperature {
+            drop(config); // Release the lock before making system calls
+            match set_night_light_temperature(optimal_temperature) {
+                Ok(()) => {
+                    let mut config = app_state.config.lock().unwrap();
+                    config.temperature = optimal_temperature;
+                    app_state.events.publish(Event::TemperatureApplied { temperature: optimal_temperature });
+                },
+                Err(e) => app_state.events.backend_error(format!("Failed to set temperature: {}", e)),
+            }
+        }
+    }
+}
+
+// Resolves with the newest frame once one arrives, or None when the capture
+// ends. Never resolves while monitoring is stopped.
+async fn next_frame(frames: &mut Option<FrameReceiver>) -> Option<Arc<FrameData>> {
+    let Some(receiver) = frames else {
+        return std::future::pending().await;
+    };
+    receiver.changed().await.ok()?;
+    receiver.borrow_and_update().clone()
+}
+
+// Background task to process frames. Analysis runs once per delivered frame;
+// the controller runs on its own interval against the latest analysis.
 async fn frame_processor(app_state: web::Data<AppState>) {
     let mut frame_count = 0u64;
-    let mut last_adjustment = Instant::now();
-    let adjustment_interval = Duration::from_secs(2);
+    let mut frames_dropped = 0u64;
+    let mut last_sequence: Option<u64> = None;
+    let mut latest_analysis: Option<FrameAnalysis> = None;
+    let mut rule_state = RuleState::default();
+    let mut motion = MotionDetector::default();
 
-    loop {
-        tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut sources = app_state.frame_receiver.subscribe();
+    let mut frames = sources.borrow_and_update().clone();
 
-        let receiver_opt = {
-            let mut frame_receiver_guard = app_state.frame_receiver.lock().unwrap();
-            if let Some(ref mut receiver) = *frame_receiver_guard {
-                receiver.try_recv().ok()
-            } else {
-                None
+    let mut control = tokio::time::interval(Duration::from_secs(2));
+    control.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            changed = sources.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                // Monitoring started or stopped
+                frames = sources.borrow_and_update().clone();
+                last_sequence = None;
+                if frames.is_none() {
+                    latest_analysis = None;
+                }
             }
-        };
+            frame = next_frame(&mut frames) => {
+                let Some(frame) = frame else {
+                    // The capture died or a replay reached its end
+                    app_state.events.backend_error("Capture stream ended".to_string());
+                    app_state.status.lock().unwrap().running = false;
+                    app_state.frame_receiver.send_replace(None);
+                    app_state.frame_source.stop();
+                    app_state.events.publish(Event::MonitoringStopped);
+                    frames = None;
+                    latest_analysis = None;
+                    continue;
+                };
 
-        if let Some(frame) = receiver_opt {
-            frame_count += 1;
-            let analysis = analyze_frame_for_nightlight(&frame);
+                if let Some(last) = last_sequence {
+                    frames_dropped += frame.sequence.saturating_sub(last + 1);
+                }
+                last_sequence = Some(frame.sequence);
+                frame_count += 1;
 
-            // Update status
-            {
-                let mut status = app_state.status.lock().unwrap();
-                status.frames_processed = frame_count;
-                status.current_analysis = Some(analysis.clone()); // Now works with Clone trait
-                status.last_update = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64;
-            }
+                if let Some(recorder) = app_state.recorder.lock().unwrap().as_mut()
+                    && let Err(e) = recorder.record(&frame)
+                {
+                    app_state.events.backend_error(format!("Failed to record frame: {}", e));
+                }
+
+                let analysis_started = Instant::now();
+                let mut analysis = analyze_frame_for_nightlight(&frame, &mut motion);
+                METRICS.observe_analysis(analysis_started.elapsed());
 
-            // Auto-adjust temperature if enabled and enough time has passed
-            if last_adjustment.elapsed() >= adjustment_interval {
-                let config = app_state.config.lock().unwrap();
-                if config.enabled {
-                    let optimal_temperature = calculate_optimal_night_light_temperature(&analysis);
-                    if optimal_temperature != config.temperature {
-                        drop(config); // Release the lock before making system calls
-                        if set_night_light_temperature(optimal_temperature).is_ok() {
-                            let mut config = app_state.config.lock().unwrap();
-                            config.temperature = optimal_temperature;
-                        }
-                    }
+                // A real light sensor beats guessing from screen contrast
+                if let Some(reading) = fresh_reading(&app_state.ambient) {
+                    analysis.ambient_light_level = reading.level;
+                    analysis.ambient_lux = Some(reading.lux);
+                    analysis.ambient_source = reading.source;
+                }
+
+                // Update status
+                let focused_app = {
+                    let mut status = app_state.status.lock().unwrap();
+                    status.frames_processed = frame_count;
+                    status.frames_dropped = frames_dropped;
+                    status.current_analysis = Some(analysis.clone()); // Now works with Clone trait
+                    status.last_update = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis() as u64;
+                    status.focused_app.clone()
+                };
+                let frame_interval_ms = app_state.config.lock().unwrap().capture.frame_interval_ms;
+                app_state.exposure.lock().unwrap().record(&analysis, focused_app.as_deref(), frame_interval_ms);
+
+                app_state.events.publish(Event::FrameAnalyzed {
+                    analysis: analysis.clone(),
+                    frames_processed: frame_count,
+                    frames_dropped,
+                });
+                latest_analysis = Some(analysis);
+            }
+            _ = control.tick() => {
+                if let Some(analysis) = latest_analysis.clone() {
+                    // Window trackers and backends run subprocesses, which would
+                    // otherwise block an executor thread
+                    let app_state = app_state.clone();
+                    let mut state = std::mem::take(&mut rule_state);
+                    rule_state = tokio::task::spawn_blocking(move || {
+                        control_step(&app_state, &mut state, &analysis);
+                        state
+                    })
+                    .await
+                    .expect("control step panicked");
                 }
-                last_adjustment = Instant::now();
             }
         }
     }
 }
 
+// Every route is served under /api/v1 and, for older clients, unversioned
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        .service(
+            web::scope("/api/v1")
+                .route("/openapi.json", web::get().to(openapi::openapi_json))
+                .service(Redoc::with_url("/docs", openapi::spec().clone()))
+                .route("/health", web::get().to(get_health))
+                .route("/auth/ticket", web::post().to(create_ticket))
+                .route("/status", web::get().to(get_status))
+                .route("/metrics", web::get().to(get_metrics))
+                .route("/ws", web::get().to(ws::events_socket))
+                .route("/events", web::get().to(sse::event_stream))
+                .route("/history", web::get().to(get_history))
+                .route("/reports/daily", web::get().to(get_daily_report))
+                .route("/reports/weekly", web::get().to(get_weekly_report))
+                .route("/sleep", web::get().to(list_sleep))
+                .route("/sleep", web::post().to(log_sleep))
+                .route("/insights/sleep", web::get().to(get_sleep_insights))
+                .route("/config", web::get().to(get_config))
+                .route("/config", web::put().to(update_config))
+                .route("/start", web::post().to(start_monitoring))
+                .route("/stop", web::post().to(stop_monitoring))
+                .route("/override", web::post().to(set_override))
+                .route("/override", web::delete().to(clear_override))
+                .route("/recording", web::post().to(start_recording))
+                .route("/recording", web::delete().to(stop_recording))
+                .route("/profiles", web::get().to(list_profiles))
+                .route("/profiles/{name}", web::get().to(get_profile))
+                .route("/profiles/{name}", web::put().to(put_profile))
+                .route("/profiles/{name}", web::delete().to(delete_profile))
+                .route("/profiles/{name}/activate", web::post().to(activate_profile))
+                .route("/rules", web::get().to(list_rules))
+                .route("/rules/{app}", web::put().to(put_rule))
+                .route("/rules/{app}", web::delete().to(delete_rule))
+                .route("/preferences", web::get().to(get_preferences))
+                .route("/preferences", web::delete().to(reset_preferences))
+        )
+        .route("/", web::get().to(get_ui))
+        .route("/health", web::get().to(get_health))
+        .route("/auth/ticket", web::post().to(create_ticket))
+        .route("/status", web::get().to(get_status))
+        .route("/metrics", web::get().to(get_metrics))
+        .route("/ws", web::get().to(ws::events_socket))
+        .route("/events", web::get().to(sse::event_stream))
+        .route("/history", web::get().to(get_history))
+        .route("/reports/daily", web::get().to(get_daily_report))
+        .route("/reports/weekly", web::get().to(get_weekly_report))
+        .route("/sleep", web::get().to(list_sleep))
+        .route("/sleep", web::post().to(log_sleep))
+        .route("/insights/sleep", web::get().to(get_sleep_insights))
+        .route("/config", web::get().to(get_config))
+        .route("/config", web::put().to(update_config))
+        .route("/start", web::post().to(start_monitoring))
+        .route("/stop", web::post().to(stop_monitoring))
+        .route("/override", web::post().to(set_override))
+        .route("/override", web::delete().to(clear_override))
+        .route("/recording", web::post().to(start_recording))
+        .route("/recording", web::delete().to(stop_recording))
+        .route("/profiles", web::get().to(list_profiles))
+        .route("/profiles/{name}", web::get().to(get_profile))
+        .route("/profiles/{name}", web::put().to(put_profile))
+        .route("/profiles/{name}", web::delete().to(delete_profile))
+        .route("/profiles/{name}/activate", web::post().to(activate_profile))
+        .route("/rules", web::get().to(list_rules))
+        .route("/rules/{app}", web::put().to(put_rule))
+        .route("/rules/{app}", web::delete().to(delete_rule))
+        .route("/preferences", web::get().to(get_preferences))
+        .route("/preferences", web::delete().to(reset_preferences));
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // Initialize simple logging instead of env_logger
     println!("Starting Adaptive Night Light Web API...");
 
+    let frame_source = match select_frame_source(std::env::args().skip(1)) {
+        Ok(frame_source) => frame_source,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: lumina_ui [--synthetic solid[:#rrggbb]|gradient|switch[:secs]|noise] [--synthetic-format BGRx|RGBx|RGB|RGBA|YUY2|I420]");
+            std::process::exit(2);
+        }
+    };
+    println!("Frame source: {}", frame_source.name());
+
+    let profile_store = ProfileStore::load(storage::config_dir().join("profiles.json"));
+    let mut initial_config = NightLightConfig::default();
+    if let Some(active) = profile_store.active() {
+        initial_config.apply_profile(&active.name, &active.profile);
+    }
+
+    let listeners = match listen::from_env() {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let token_path = storage::config_dir().join("api-token");
+    let auth = match Auth::load_or_create(&token_path) {
+        Ok(auth) => auth,
+        Err(e) => {
+            eprintln!("Failed to read or create the API token {}: {}", token_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    println!("API token: {}", token_path.display());
+
+    // Cross-origin callers besides the bundled UI, e.g. a dev server
+    let allowed_origins: Vec<String> = std::env::var("LUMINA_ALLOWED_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+
+    let window_tracker = detect_window_tracker();
+    println!("Focused window tracking: {}", window_tracker.name());
+
     let app_state = web::Data::new(AppState {
         status: Arc::new(Mutex::new(SystemStatus {
             running: false,
             frames_processed: 0,
+            frames_dropped: 0,
             current_analysis: None,
             current_config: NightLightConfig::default(),
             last_update: 0,
+            override_remaining_secs: None,
+            capture_source: frame_source.name(),
+            focused_app: None,
+            fullscreen: false,
+            high_motion: false,
+            adjustments_frozen: false,
+            capture_stream: None,
         })),
-        config: Arc::new(Mutex::new(NightLightConfig::default())),
-        frame_receiver: Arc::new(Mutex::new(None)),
+        config: Arc::new(Mutex::new(initial_config)),
+        frame_receiver: watch::channel(None).0,
+        preferences: Arc::new(Mutex::new(PreferenceLearner::load(
+            storage::config_dir().join("preferences.json"),
+        ))),
+        profiles: Arc::new(Mutex::new(profile_store)),
+        rules: Arc::new(Mutex::new(RuleStore::load(storage::config_dir().join("rules.json")))),
+        window_tracker: Arc::from(window_tracker),
+        ambient: Arc::new(Mutex::new(None)),
+        frame_source,
+        recorder: Arc::new(Mutex::new(None)),
+        events: EventBus::new(),
+        history: Arc::new(Mutex::new(HistoryStore::load(storage::config_dir().join("history.jsonl")))),
+        exposure: Arc::new(Mutex::new(ExposureTracker::load(storage::config_dir().join("exposure.json")))),
+        sleep: Arc::new(Mutex::new(SleepJournal::load(storage::config_dir().join("sleep.json")))),
+        auth,
+    });
+
+    let ambient = app_state.ambient.clone();
+    tokio::spawn(async move {
+        run_ambient_sensors(ambient).await;
+    });
+
+    let history = app_state.history.clone();
+    let history_events = app_state.events.subscribe();
+    tokio::spawn(async move {
+        history::record_events(history, history_events).await;
     });
 
     // Start background frame processor
@@ -535,43 +1896,564 @@ async fn main() -> std::io::Result<()> {
 
     println!("🌙 Adaptive Night Light Web API");
     println!("================================");
-    println!("Server starting at http://localhost:8080");
+    for listener in &listeners {
+        println!("Server starting at {}", listener);
+    }
     println!();
     println!("Available endpoints:");
+    println!("  GET    /api/v1/openapi.json - OpenAPI description of every endpoint");
+    println!("  GET    /api/v1/docs    - API documentation");
+    println!("  GET    /               - Web UI (open with #token=<API token>)");
     println!("  GET    /health         - Health check");
+    println!("  POST   /auth/ticket    - Short-lived ticket for /ws and /events");
     println!("  GET    /status         - System status");
+    println!("  GET    /metrics        - Prometheus metrics");
+    println!("  GET    /ws             - Live events and config commands (WebSocket)");
+    println!("  GET    /events         - Live events (Server-Sent Events)");
+    println!("  GET    /history        - Analysis and temperature history");
+    println!("  GET    /reports/daily  - Blue-light exposure for a day (JSON or CSV)");
+    println!("  GET    /reports/weekly - Blue-light exposure for a week (JSON or CSV)");
+    println!("  GET    /sleep          - Logged sleep latencies");
+    println!("  POST   /sleep          - Log last night's sleep latency");
+    println!("  GET    /insights/sleep - Sleep-latency model and its cross-validated accuracy");
     println!("  GET    /config         - Current configuration");
     println!("  PUT    /config         - Update configuration");
     println!("  POST   /start          - Start monitoring");
     println!("  POST   /stop           - Stop monitoring");
+    println!("  POST   /override       - Hold a temperature for a while");
+    println!("  DELETE /override       - Cancel the held temperature");
+    println!("  POST   /recording      - Start recording captured frames");
+    println!("  DELETE /recording      - Finish the recording");
+    println!("  GET    /profiles       - List profiles");
+    println!("  GET    /profiles/{{name}} - Show a profile");
+    println!("  PUT    /profiles/{{name}} - Create or replace a profile");
+    println!("  DELETE /profiles/{{name}} - Delete a profile");
+    println!("  POST   /profiles/{{name}}/activate - Switch to a profile");
+    println!("  GET    /rules          - List per-application rules");
+    println!("  PUT    /rules/{{app}}    - Create or replace a rule");
+    println!("  DELETE /rules/{{app}}    - Delete a rule");
+    println!("  GET    /preferences    - Learned preference model");
+    println!("  DELETE /preferences    - Reset learned preferences");
     println!();
 
-    HttpServer::new(move || {
+    let exposure = app_state.exposure.clone();
+    let mut server = HttpServer::new(move || {
+        let allowed_origins = allowed_origins.clone();
+        // The bundled UI is served from here, so its own origin is always allowed
         let cors = Cors::default()
-        .allow_any_origin()
-        .allow_any_method()
-        .allow_any_header();
+            .allowed_origin_fn(move |origin, req| {
+                let origin = origin.to_str().unwrap_or_default();
+                let same_origin = req
+                    .headers()
+                    .get(actix_web::http::header::HOST)
+                    .and_then(|host| host.to_str().ok())
+                    .is_some_and(|host| origin.strip_prefix("http://") == Some(host));
+                same_origin || allowed_origins.iter().any(|allowed| allowed == origin)
+            })
+            .allowed_methods(["GET", "POST", "PUT", "DELETE"])
+            .allowed_headers([actix_web::http::header::AUTHORIZATION, actix_web::http::header::CONTENT_TYPE])
+            .block_on_origin_mismatch(true)
+            .max_age(3600);
         App::new()
+            .wrap(actix_web::middleware::from_fn(auth::require_token))
             .wrap(cors)
             .app_data(app_state.clone())
+            .app_data(error::json_config())
+            .app_data(error::query_config())
             .wrap(Logger::default())
-            .service(
-                web::scope("/api/v1")
-                    .route("/health", web::get().to(get_health))
-                    .route("/status", web::get().to(get_status))
-                    .route("/config", web::get().to(get_config))
-                    .route("/config", web::put().to(update_config))
-                    .route("/start", web::post().to(start_monitoring))
-                    .route("/stop", web::post().to(stop_monitoring))
-            )
-            .route("/health", web::get().to(get_health))
-            .route("/status", web::get().to(get_status))
-            .route("/config", web::get().to(get_config))
-            .route("/config", web::put().to(update_config))
-            .route("/start", web::post().to(start_monitoring))
-            .route("/stop", web::post().to(stop_monitoring))
-    })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
-}
\ No newline at end of file
+            .configure(routes)
+    });
+
+    for listener in &listeners {
+        let bound = match listener {
+            listen::Listen::Tcp(address) => server.bind(address),
+            listen::Listen::Unix(path) => listen::bind_unix(path).and_then(|socket| server.listen_uds(socket)),
+        };
+        server = match bound {
+            Ok(server) => server,
+            Err(e) => {
+                eprintln!("Failed to listen on {}: {}", listener, e);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    let result = server.run().await;
+    exposure.lock().unwrap().flush();
+    for listener in &listeners {
+        if let listen::Listen::Unix(path) = listener {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use window_tracker::{FocusedWindow, MockWindowTracker};
+
+    pub(crate) fn test_state(name: &str, tracker: Arc<MockWindowTracker>) -> AppState {
+        let dir = storage::test_dir(name);
+        let frame_source = select_frame_source(["--synthetic".to_string(), "solid".to_string()]).unwrap();
+        AppState {
+            status: Arc::new(Mutex::new(SystemStatus {
+                running: false,
+                frames_processed: 0,
+                frames_dropped: 0,
+                current_analysis: None,
+                current_config: NightLightConfig::default(),
+                last_update: 0,
+                override_remaining_secs: None,
+                capture_source: frame_source.name(),
+                focused_app: None,
+                fullscreen: false,
+                high_motion: false,
+                adjustments_frozen: false,
+                capture_stream: None,
+            })),
+            // A fixed policy keeps adaptation from moving the temperature under the tests
+            config: Arc::new(Mutex::new(NightLightConfig {
+                enabled: true,
+                policy: TemperaturePolicy::Fixed,
+                ..NightLightConfig::default()
+            })),
+            frame_receiver: watch::channel(None).0,
+            preferences: Arc::new(Mutex::new(PreferenceLearner::load(dir.join("preferences.json")))),
+            profiles: Arc::new(Mutex::new(ProfileStore::load(dir.join("profiles.json")))),
+            rules: Arc::new(Mutex::new(RuleStore::load(dir.join("rules.json")))),
+            window_tracker: tracker,
+            ambient: Arc::new(Mutex::new(None)),
+            frame_source,
+            recorder: Arc::new(Mutex::new(None)),
+            events: EventBus::new(),
+            history: Arc::new(Mutex::new(HistoryStore::load(dir.join("history.jsonl")))),
+            exposure: Arc::new(Mutex::new(ExposureTracker::load(dir.join("exposure.json")))),
+            sleep: Arc::new(Mutex::new(SleepJournal::load(dir.join("sleep.json")))),
+            auth: Auth::load_or_create(&dir.join("api-token")).unwrap(),
+        }
+    }
+
+    fn focus(tracker: &MockWindowTracker, app_id: Option<&str>) {
+        tracker.set_focused(app_id.map(|app_id| FocusedWindow {
+            app_id: app_id.to_string(),
+            title: String::new(),
+            fullscreen: false,
+        }));
+    }
+
+    fn profile(temperature: u32) -> Profile {
+        Profile {
+            min_temperature: 2000,
+            max_temperature: 6500,
+            policy: TemperaturePolicy::Fixed,
+            temperature,
+            smoothing: 0.0,
+            brightness: None,
+        }
+    }
+
+    // A dim, still screen; the rules don't look at it
+    fn analysis() -> FrameAnalysis {
+        FrameAnalysis {
+            average_brightness: 40.0,
+            blue_intensity: 0.3,
+            ambient_light_level: 0.2,
+            ambient_source: "screen",
+            ambient_lux: None,
+            motion_level: 0.0,
+            timestamp: 0,
+            frame_size: 0,
+        }
+    }
+
+    fn take_desktop_calls() -> Vec<String> {
+        DESKTOP_CALLS.with(|calls| std::mem::take(&mut *calls.borrow_mut()))
+    }
+
+    #[test]
+    fn disable_rule_suspends_night_light_while_focused() {
+        let tracker = Arc::new(MockWindowTracker::default());
+        let state = test_state("disable-rule", tracker.clone());
+        state.rules.lock().unwrap().upsert("Blender", RuleAction::Disable).unwrap();
+        let mut rule_state = RuleState::default();
+        take_desktop_calls();
+
+        // Matched by the last segment of the app id, case-insensitively
+        focus(&tracker, Some("org.blender.blender"));
+        control_step(&state, &mut rule_state, &analysis());
+        assert!(rule_state.suspended);
+        assert_eq!(state.status.lock().unwrap().focused_app.as_deref(), Some("org.blender.blender"));
+        assert_eq!(
+            take_desktop_calls(),
+            ["gsettings set org.gnome.settings-daemon.plugins.color night-light-enabled false"]
+        );
+
+        // Staying focused doesn't repeat the call
+        control_step(&state, &mut rule_state, &analysis());
+        assert!(take_desktop_calls().is_empty());
+
+        focus(&tracker, Some("org.gnome.Nautilus"));
+        control_step(&state, &mut rule_state, &analysis());
+        assert!(!rule_state.suspended);
+        assert_eq!(
+            take_desktop_calls(),
+            [
+                "gsettings set org.gnome.settings-daemon.plugins.color night-light-enabled true",
+                "gsettings set org.gnome.settings-daemon.plugins.color night-light-schedule-automatic false",
+                "gsettings set org.gnome.settings-daemon.plugins.color night-light-temperature 4000",
+            ]
+        );
+    }
+
+    #[test]
+    fn switch_profile_rule_reverts_when_focus_leaves() {
+        let tracker = Arc::new(MockWindowTracker::default());
+        let state = test_state("switch-rule", tracker.clone());
+        state.profiles.lock().unwrap().upsert("reading", profile(2700)).unwrap();
+        state.rules.lock().unwrap()
+            .upsert("evince", RuleAction::SwitchProfile { profile: "reading".to_string() })
+            .unwrap();
+        let mut rule_state = RuleState::default();
+
+        focus(&tracker, Some("evince"));
+        control_step(&state, &mut rule_state, &analysis());
+        {
+            let config = state.config.lock().unwrap();
+            assert_eq!(config.active_profile.as_deref(), Some("reading"));
+            assert_eq!(config.temperature, 2700);
+        }
+
+        focus(&tracker, None);
+        control_step(&state, &mut rule_state, &analysis());
+        let config = state.config.lock().unwrap();
+        assert_eq!(config.active_profile, None);
+        assert_eq!(config.temperature, 4000);
+        assert!(rule_state.profile_snapshot.is_none());
+        assert_eq!(state.status.lock().unwrap().focused_app, None);
+    }
+
+    #[test]
+    fn profile_rules_announce_config_changes() {
+        let tracker = Arc::new(MockWindowTracker::default());
+        let state = test_state("rule-events", tracker.clone());
+        state.profiles.lock().unwrap().upsert("reading", profile(2700)).unwrap();
+        state.rules.lock().unwrap()
+            .upsert("evince", RuleAction::SwitchProfile { profile: "reading".to_string() })
+            .unwrap();
+        let mut events = state.events.subscribe();
+        let mut rule_state = RuleState::default();
+
+        let mut next_config = || match events.try_recv().unwrap().event {
+            Event::ConfigChanged { config } => config,
+            other => panic!("unexpected event {:?}", other),
+        };
+
+        focus(&tracker, Some("evince"));
+        control_step(&state, &mut rule_state, &analysis());
+        assert_eq!(next_config().active_profile.as_deref(), Some("reading"));
+
+        focus(&tracker, None);
+        control_step(&state, &mut rule_state, &analysis());
+        assert_eq!(next_config().active_profile, None);
+    }
+
+    #[actix_web::test]
+    async fn editing_the_active_profile_announces_the_config() {
+        let state = web::Data::new(test_state("put-profile", Arc::new(MockWindowTracker::default())));
+        state.config.lock().unwrap().active_profile = Some("reading".to_string());
+        let mut events = state.events.subscribe();
+
+        put_profile(state.clone(), web::Path::from("reading".to_string()), web::Json(profile(2900))).await.unwrap();
+        match events.try_recv().unwrap().event {
+            Event::ConfigChanged { config } => assert_eq!(config.temperature, 2900),
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        // Other profiles don't touch the config
+        put_profile(state.clone(), web::Path::from("gaming".to_string()), web::Json(profile(5000))).await.unwrap();
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn failing_desktop_commands_are_errors() {
+        DESKTOP_EXIT_CODE.set(1);
+        let error = set_night_light_temperature(3500).unwrap_err();
+        assert!(error.to_string().contains("No such schema"), "{}", error);
+        assert!(enable_night_light().is_err());
+        // The first failure stops the sequence
+        assert_eq!(take_desktop_calls().len(), 2);
+
+        DESKTOP_EXIT_CODE.set(0);
+        assert!(set_night_light_temperature(3500).is_ok());
+    }
+
+    #[test]
+    fn failed_disable_leaves_the_rule_unapplied() {
+        let tracker = Arc::new(MockWindowTracker::default());
+        let state = test_state("failed-disable", tracker.clone());
+        state.rules.lock().unwrap().upsert("mpv", RuleAction::Disable).unwrap();
+        let mut rule_state = RuleState::default();
+
+        DESKTOP_EXIT_CODE.set(1);
+        focus(&tracker, Some("mpv"));
+        control_step(&state, &mut rule_state, &analysis());
+        DESKTOP_EXIT_CODE.set(0);
+        assert!(!rule_state.suspended);
+    }
+
+    #[test]
+    fn manual_profile_switch_survives_the_rule_ending() {
+        let tracker = Arc::new(MockWindowTracker::default());
+        let state = test_state("manual-switch", tracker.clone());
+        {
+            let mut profiles = state.profiles.lock().unwrap();
+            profiles.upsert("reading", profile(2700)).unwrap();
+            profiles.upsert("gaming", profile(5000)).unwrap();
+        }
+        state.rules.lock().unwrap()
+            .upsert("evince", RuleAction::SwitchProfile { profile: "reading".to_string() })
+            .unwrap();
+        let mut rule_state = RuleState::default();
+
+        focus(&tracker, Some("evince"));
+        control_step(&state, &mut rule_state, &analysis());
+
+        // The user picks another profile while the rule is in force
+        let gaming = state.profiles.lock().unwrap().get("gaming").unwrap();
+        activate_profile_settings(&mut state.config.lock().unwrap(), &gaming.name, &gaming.profile).unwrap();
+
+        focus(&tracker, Some("steam"));
+        control_step(&state, &mut rule_state, &analysis());
+        let config = state.config.lock().unwrap();
+        assert_eq!(config.active_profile.as_deref(), Some("gaming"));
+        assert_eq!(config.temperature, 5000);
+    }
+
+    #[test]
+    fn recording_names_stay_in_the_recordings_directory() {
+        let recordings = storage::config_dir().join("recordings");
+        assert_eq!(recording_path(Some("evening.lrec")).unwrap(), recordings.join("evening.lrec"));
+        assert_eq!(recording_path(None).unwrap().parent(), Some(recordings.as_path()));
+
+        for name in ["", ".", "..", "../api-token", "/tmp/x.lrec", "a/b", "a\\b"] {
+            assert!(matches!(recording_path(Some(name)), Err(ApiError::InvalidField { .. })), "{}", name);
+        }
+    }
+
+    fn moving(motion_level: f64) -> FrameAnalysis {
+        FrameAnalysis { motion_level, ..analysis() }
+    }
+
+    fn solid_frame(value: u8) -> FrameData {
+        FrameData {
+            data: vec![value; 64 * 36 * 4],
+            width: 64,
+            height: 36,
+            format: pipewire::spa::param::video::VideoFormat::BGRx,
+            timestamp: std::time::Instant::now(),
+            sequence: 0,
+        }
+    }
+
+    #[test]
+    fn motion_freezes_adjustment_while_it_lasts() {
+        let state = test_state("motion-freeze", Arc::new(MockWindowTracker::default()));
+        {
+            let mut config = state.config.lock().unwrap();
+            config.policy = TemperaturePolicy::Adaptive;
+            config.freeze_on_motion = true;
+        }
+        let mut rule_state = RuleState::default();
+        take_desktop_calls();
+
+        // Full-screen flicker, as a video would give, then a still screen
+        let (black, white) = (solid_frame(0), solid_frame(255));
+        let mut detector = MotionDetector::default();
+        let mut frozen = Vec::new();
+        let mut adjusted = Vec::new();
+        for index in 0..20 {
+            let frame = if index < 10 && index % 2 == 1 { &white } else { &black };
+            // Below the policy's bounds, so every unfrozen step adjusts
+            state.config.lock().unwrap().temperature = 2000;
+            control_step(&state, &mut rule_state, &moving(detector.update(frame)));
+            frozen.push(state.status.lock().unwrap().adjustments_frozen);
+            adjusted.push(!take_desktop_calls().is_empty());
+        }
+
+        // The first frame has nothing to compare with and the next one trips
+        // the threshold. The last change is back to black at frame 10; four
+        // still frames after it the smoothed level is still above the
+        // threshold, and the fifth releases the hold.
+        let expected: Vec<bool> = (0..20).map(|index| (1..15).contains(&index)).collect();
+        assert_eq!(frozen, expected);
+        let unfrozen: Vec<bool> = expected.iter().map(|frozen| !frozen).collect();
+        assert_eq!(adjusted, unfrozen);
+        assert!(!state.status.lock().unwrap().high_motion);
+    }
+
+    #[test]
+    fn motion_is_ignored_unless_enabled() {
+        let state = test_state("motion-off", Arc::new(MockWindowTracker::default()));
+        assert!(!NightLightConfig::default().freeze_on_motion);
+        let mut rule_state = RuleState::default();
+
+        control_step(&state, &mut rule_state, &moving(0.9));
+        let status = state.status.lock().unwrap();
+        assert!(status.high_motion);
+        assert!(!status.adjustments_frozen);
+    }
+
+    fn hold(temperature: u32, duration_minutes: Option<u64>) -> web::Json<OverrideRequest> {
+        web::Json(OverrideRequest { temperature, duration_minutes, until_next_transition: None })
+    }
+
+    #[actix_web::test]
+    async fn override_holds_temperature_until_it_expires() {
+        let state = web::Data::new(test_state("override-hold", Arc::new(MockWindowTracker::default())));
+        state.config.lock().unwrap().policy = TemperaturePolicy::Adaptive;
+        let mut rule_state = RuleState::default();
+        take_desktop_calls();
+
+        for invalid in [hold(500, Some(30)), hold(3000, None), hold(3000, Some(0))] {
+            let result = set_override(state.clone(), invalid).await;
+            assert!(matches!(result, Err(ApiError::InvalidField { .. })));
+        }
+        assert!(take_desktop_calls().is_empty());
+
+        let mut events = state.events.subscribe();
+        let before = unix_millis();
+        set_override(state.clone(), hold(2500, Some(30))).await.unwrap();
+        {
+            let config = state.config.lock().unwrap();
+            let manual_override = config.manual_override.as_ref().unwrap();
+            assert_eq!((config.temperature, manual_override.temperature), (2500, 2500));
+            assert!((before + 30 * 60_000..=unix_millis() + 30 * 60_000).contains(&manual_override.expires_at));
+        }
+        assert_eq!(
+            take_desktop_calls().last().map(String::as_str),
+            Some("gsettings set org.gnome.settings-daemon.plugins.color night-light-temperature 2500")
+        );
+        assert!(matches!(events.try_recv().unwrap().event, Event::TemperatureApplied { temperature: 2500 }));
+        assert!(matches!(events.try_recv().unwrap().event, Event::ConfigChanged { .. }));
+        assert!(status_snapshot(&state).override_remaining_secs.is_some_and(|secs| secs > 29 * 60));
+
+        // The policy leaves the held temperature alone
+        control_step(&state, &mut rule_state, &analysis());
+        assert_eq!(state.config.lock().unwrap().temperature, 2500);
+        assert!(take_desktop_calls().is_empty());
+
+        // Once it lapses the controller clears it and adapts again
+        state.config.lock().unwrap().manual_override.as_mut().unwrap().expires_at = unix_millis() - 1;
+        control_step(&state, &mut rule_state, &analysis());
+        {
+            let config = state.config.lock().unwrap();
+            assert!(config.manual_override.is_none());
+            assert_ne!(config.temperature, 2500);
+        }
+        assert!(matches!(events.try_recv().unwrap().event, Event::ConfigChanged { config } if config.manual_override.is_none()));
+        assert_eq!(status_snapshot(&state).override_remaining_secs, None);
+    }
+
+    #[actix_web::test]
+    async fn expired_override_is_cleared_while_stopped() {
+        let state = web::Data::new(test_state("override-stopped", Arc::new(MockWindowTracker::default())));
+        set_override(state.clone(), hold(2500, Some(30))).await.unwrap();
+        state.config.lock().unwrap().manual_override.as_mut().unwrap().expires_at = unix_millis() - 1;
+        let mut events = state.events.subscribe();
+
+        // No control step runs; reading the config is enough
+        let response = get_config(state.clone()).await.unwrap();
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        let config: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(config["manual_override"].is_null());
+        assert!(state.config.lock().unwrap().manual_override.is_none());
+        assert!(matches!(events.try_recv().unwrap().event, Event::ConfigChanged { .. }));
+
+        // And only once
+        get_config(state.clone()).await.unwrap();
+        assert!(events.try_recv().is_err());
+    }
+
+    #[actix_web::test]
+    async fn config_temperature_updates_the_hold() {
+        let state = web::Data::new(test_state("override-update", Arc::new(MockWindowTracker::default())));
+        set_override(state.clone(), hold(2500, Some(30))).await.unwrap();
+
+        apply_config_update(&state, &config_update(serde_json::json!({ "temperature": 3100 }))).unwrap();
+        let config = state.config.lock().unwrap().clone();
+        assert_eq!(config.temperature, 3100);
+        assert_eq!(config.manual_override.as_ref().unwrap().temperature, 3100);
+
+        // Cancelling the hold keeps the temperature
+        clear_override(state.clone()).await.unwrap();
+        let config = state.config.lock().unwrap();
+        assert!(config.manual_override.is_none());
+        assert_eq!(config.temperature, 3100);
+    }
+
+    fn config_update(body: serde_json::Value) -> UpdateConfigRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn rejected_config_updates_change_nothing() {
+        let state = test_state("config-rejected", Arc::new(MockWindowTracker::default()));
+        let before = state.config.lock().unwrap().clone();
+        let mut events = state.events.subscribe();
+        take_desktop_calls();
+
+        let invalid = [
+            serde_json::json!({ "adaptation_pause_secs": 60, "freeze_on_motion": false, "motion_threshold": 1.5 }),
+            serde_json::json!({ "freeze_on_fullscreen": false, "temperature": 20000 }),
+            serde_json::json!({ "adaptation_pause_secs": 60, "capture": { "max_framerate": 0 } }),
+        ];
+        for body in invalid {
+            let result = apply_config_update(&state, &config_update(body.clone()));
+            assert!(matches!(result, Err(ApiError::InvalidField { .. })), "{}", body);
+        }
+
+        let after = state.config.lock().unwrap().clone();
+        assert_eq!(serde_json::to_value(after).unwrap(), serde_json::to_value(before).unwrap());
+        assert!(events.try_recv().is_err());
+        assert!(take_desktop_calls().is_empty());
+    }
+
+    #[test]
+    fn config_updates_apply_together_and_announce_once() {
+        let state = test_state("config-update", Arc::new(MockWindowTracker::default()));
+        let mut events = state.events.subscribe();
+
+        let body = serde_json::json!({ "adaptation_pause_secs": 60, "motion_threshold": 0.4, "temperature": 3300 });
+        let config = apply_config_update(&state, &config_update(body)).unwrap();
+        assert_eq!((config.adaptation_pause_secs, config.motion_threshold, config.temperature), (60, 0.4, 3300));
+
+        assert!(matches!(events.try_recv().unwrap().event, Event::TemperatureApplied { temperature: 3300 }));
+        match events.try_recv().unwrap().event {
+            Event::ConfigChanged { config } => assert_eq!(config.motion_threshold, 0.4),
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(events.try_recv().is_err());
+
+        assert!(matches!(apply_config_update(&state, &config_update(serde_json::json!({}))), Err(ApiError::NoChanges)));
+    }
+
+    #[test]
+    fn smoothing_converges_on_target() {
+        for (start, target) in [(6500, 3400), (3400, 6500)] {
+            let mut temperature = start;
+            for _ in 0..10_000 {
+                temperature = smooth_temperature(temperature, target, 0.98);
+            }
+            assert_eq!(temperature, target);
+        }
+    }
+
+    #[test]
+    fn smoothing_steps_at_least_one_kelvin() {
+        assert_eq!(smooth_temperature(4000, 4010, 0.99), 4001);
+        assert_eq!(smooth_temperature(4010, 4000, 0.99), 4009);
+        assert_eq!(smooth_temperature(4000, 4000, 0.99), 4000);
+        assert_eq!(smooth_temperature(4000, 5000, 0.0), 5000);
+    }
+}
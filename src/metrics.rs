@@ -0,0 +1,212 @@
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::SystemStatus;
+
+// Upper bounds in seconds, shared by every histogram. Analysis usually lands
+// in the low milliseconds, a gsettings call in the tens.
+const BUCKETS: [f64; 12] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        // Buckets hold only their own observations; render() accumulates them
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| secs <= bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let (bucket_labels, series_labels) = match labels {
+            "" => (String::new(), String::new()),
+            labels => (format!("{},", labels), format!("{{{}}}", labels)),
+        };
+
+        let mut cumulative = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{{}le=\"{}\"}} {}", name, bucket_labels, bound, cumulative);
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, bucket_labels, count);
+        let _ = writeln!(out, "{}_sum{} {}", name, series_labels, sum);
+        let _ = writeln!(out, "{}_count{} {}", name, series_labels, count);
+    }
+}
+
+// Calls out to the desktop that change or read the display settings
+#[derive(Debug, Clone, Copy)]
+pub enum BackendCall {
+    SetTemperature,
+    EnableNightLight,
+    DisableNightLight,
+    SetBrightness,
+    ReadSchedule,
+}
+
+const BACKEND_CALLS: [BackendCall; 5] = [
+    BackendCall::SetTemperature,
+    BackendCall::EnableNightLight,
+    BackendCall::DisableNightLight,
+    BackendCall::SetBrightness,
+    BackendCall::ReadSchedule,
+];
+
+impl BackendCall {
+    fn name(self) -> &'static str {
+        match self {
+            BackendCall::SetTemperature => "set_temperature",
+            BackendCall::EnableNightLight => "enable_night_light",
+            BackendCall::DisableNightLight => "disable_night_light",
+            BackendCall::SetBrightness => "set_brightness",
+            BackendCall::ReadSchedule => "read_schedule",
+        }
+    }
+}
+
+// Process-wide, since the backend calls are plain functions with no state
+// to hang counters on
+pub struct Metrics {
+    analysis: Histogram,
+    backend_calls: [Histogram; BACKEND_CALLS.len()],
+    backend_failures: [AtomicU64; BACKEND_CALLS.len()],
+}
+
+pub static METRICS: Metrics = Metrics {
+    analysis: Histogram::new(),
+    backend_calls: [const { Histogram::new() }; BACKEND_CALLS.len()],
+    backend_failures: [const { AtomicU64::new(0) }; BACKEND_CALLS.len()],
+};
+
+impl Metrics {
+    pub fn observe_analysis(&self, duration: Duration) {
+        self.analysis.observe(duration);
+    }
+
+    // Runs a backend call, timing it and counting it if it fails
+    pub fn time_backend<T, E>(&self, call: BackendCall, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let started = Instant::now();
+        let result = f();
+        self.backend_calls[call as usize].observe(started.elapsed());
+        if result.is_err() {
+            self.backend_failures[call as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    // Prometheus text exposition format, version 0.0.4
+    pub fn render(&self, status: &SystemStatus, temperature: u32) -> String {
+        let mut out = String::new();
+
+        gauge(&mut out, "lumina_running", "Whether the screen is being monitored", status.running as u8 as f64);
+        gauge(&mut out, "lumina_temperature_kelvin", "Current night light temperature", temperature as f64);
+        if let Some(analysis) = &status.current_analysis {
+            gauge(&mut out, "lumina_screen_brightness", "Average brightness of the latest frame (0-255)", analysis.average_brightness);
+            gauge(&mut out, "lumina_blue_intensity", "Average blue intensity of the latest frame (0-255)", analysis.blue_intensity);
+            gauge(&mut out, "lumina_ambient_light_level", "Estimated or measured ambient light (0-1)", analysis.ambient_light_level);
+        }
+
+        counter(&mut out, "lumina_frames_processed_total", "Frames analysed since startup", status.frames_processed);
+        counter(&mut out, "lumina_frames_dropped_total", "Frames superseded before they could be analysed", status.frames_dropped);
+
+        let _ = writeln!(out, "# HELP lumina_analysis_duration_seconds Time spent analysing one frame");
+        let _ = writeln!(out, "# TYPE lumina_analysis_duration_seconds histogram");
+        self.analysis.render(&mut out, "lumina_analysis_duration_seconds", "");
+
+        let _ = writeln!(out, "# HELP lumina_backend_call_duration_seconds Latency of calls to the desktop settings");
+        let _ = writeln!(out, "# TYPE lumina_backend_call_duration_seconds histogram");
+        for call in BACKEND_CALLS {
+            let labels = format!("call=\"{}\"", call.name());
+            self.backend_calls[call as usize].render(&mut out, "lumina_backend_call_duration_seconds", &labels);
+        }
+
+        let _ = writeln!(out, "# HELP lumina_backend_failures_total Calls to the desktop settings that failed");
+        let _ = writeln!(out, "# TYPE lumina_backend_failures_total counter");
+        for call in BACKEND_CALLS {
+            let failures = self.backend_failures[call as usize].load(Ordering::Relaxed);
+            let _ = writeln!(out, "lumina_backend_failures_total{{call=\"{}\"}} {}", call.name(), failures);
+        }
+
+        out
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new();
+        for millis in [0.2, 0.8, 0.9, 30.0, 4_000.0] {
+            histogram.observe(Duration::from_secs_f64(millis / 1000.0));
+        }
+
+        let mut out = String::new();
+        histogram.render(&mut out, "test_seconds", "call=\"x\"");
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines[0], "test_seconds_bucket{call=\"x\",le=\"0.0005\"} 1");
+        assert_eq!(lines[1], "test_seconds_bucket{call=\"x\",le=\"0.001\"} 3");
+        assert_eq!(lines[5], "test_seconds_bucket{call=\"x\",le=\"0.025\"} 3");
+        assert_eq!(lines[6], "test_seconds_bucket{call=\"x\",le=\"0.05\"} 4");
+        // Past the last bound only +Inf counts it
+        assert_eq!(lines[11], "test_seconds_bucket{call=\"x\",le=\"2.5\"} 4");
+        assert_eq!(lines[12], "test_seconds_bucket{call=\"x\",le=\"+Inf\"} 5");
+        assert!(lines[13].starts_with("test_seconds_sum{call=\"x\"} 4.031"));
+        assert_eq!(lines[14], "test_seconds_count{call=\"x\"} 5");
+        assert_eq!(lines.len(), BUCKETS.len() + 3);
+    }
+
+    #[test]
+    fn unlabelled_histogram_has_bare_series() {
+        let histogram = Histogram::new();
+        histogram.observe(Duration::from_millis(3));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "test_seconds", "");
+        assert!(out.starts_with("test_seconds_bucket{le=\"0.0005\"} 0\n"));
+        assert!(out.contains("\ntest_seconds_count 1\n"));
+    }
+
+    #[test]
+    fn failed_backend_calls_are_counted() {
+        let metrics = Metrics {
+            analysis: Histogram::new(),
+            backend_calls: [const { Histogram::new() }; BACKEND_CALLS.len()],
+            backend_failures: [const { AtomicU64::new(0) }; BACKEND_CALLS.len()],
+        };
+        let _ = metrics.time_backend(BackendCall::SetBrightness, || Err::<(), _>("exit status 1"));
+        let _ = metrics.time_backend(BackendCall::SetBrightness, || Ok::<_, ()>(()));
+
+        let call = BackendCall::SetBrightness as usize;
+        assert_eq!(metrics.backend_failures[call].load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.backend_calls[call].count.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,140 @@
+use pipewire::spa::param::video::VideoFormat;
+
+use crate::capture::FrameData;
+
+// Sampling grid for the luma thumbnails that get compared frame to frame
+const GRID_WIDTH: u32 = 64;
+const GRID_HEIGHT: u32 = 36;
+// Weight of the newest difference in the running motion level
+const MOTION_SMOOTHING: f64 = 0.4;
+
+// Frame-to-frame differencing on a coarse luma grid. Video and games keep
+// the running level high; scrolling or typing only produce brief spikes.
+#[derive(Default)]
+pub struct MotionDetector {
+    previous: Option<Vec<u8>>,
+    level: f64,
+}
+
+impl MotionDetector {
+    // Feeds a frame and returns the smoothed motion level in [0.0, 1.0]
+    pub fn update(&mut self, frame: &FrameData) -> f64 {
+        let Some(thumbnail) = luma_thumbnail(frame) else {
+            return self.level;
+        };
+
+        if let Some(previous) = &self.previous {
+            let difference: u64 = previous
+                .iter()
+                .zip(&thumbnail)
+                .map(|(&a, &b)| a.abs_diff(b) as u64)
+                .sum();
+            let instant = difference as f64 / (thumbnail.len() as f64 * 255.0);
+            self.level = self.level * (1.0 - MOTION_SMOOTHING) + instant * MOTION_SMOOTHING;
+        }
+
+        self.previous = Some(thumbnail);
+        self.level
+    }
+}
+
+fn luma_thumbnail(frame: &FrameData) -> Option<Vec<u8>> {
+    let (bytes_per_pixel, r, g, b) = match frame.format {
+        VideoFormat::RGB => (3, 0, 1, 2),
+        VideoFormat::RGBA | VideoFormat::RGBx => (4, 0, 1, 2),
+        VideoFormat::BGRx => (4, 2, 1, 0),
+        _ => return None,
+    };
+
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    if width == 0 || height == 0 || frame.data.len() < width * height * bytes_per_pixel {
+        return None;
+    }
+
+    let mut thumbnail = Vec::with_capacity((GRID_WIDTH * GRID_HEIGHT) as usize);
+    for gy in 0..GRID_HEIGHT as usize {
+        let y = gy * height / GRID_HEIGHT as usize;
+        for gx in 0..GRID_WIDTH as usize {
+            let x = gx * width / GRID_WIDTH as usize;
+            let offset = (y * width + x) * bytes_per_pixel;
+            let pixel = &frame.data[offset..offset + bytes_per_pixel];
+            let luma = (299 * pixel[r] as u32 + 587 * pixel[g] as u32 + 114 * pixel[b] as u32) / 1000;
+            thumbnail.push(luma as u8);
+        }
+    }
+
+    Some(thumbnail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    // A horizontal ramp, shifted right by `shift` pixels
+    fn ramp(width: u32, height: u32, shift: u32) -> FrameData {
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for _ in 0..height {
+            for x in 0..width {
+                let value = ((x + width - shift) % width * 255 / (width - 1)) as u8;
+                data.extend_from_slice(&[value, value, value, 255]);
+            }
+        }
+        FrameData { data, width, height, format: VideoFormat::BGRx, timestamp: Instant::now(), sequence: 0 }
+    }
+
+    #[test]
+    fn identical_frames_show_no_motion() {
+        let mut detector = MotionDetector::default();
+        for _ in 0..5 {
+            assert_eq!(detector.update(&ramp(640, 360, 0)), 0.0);
+        }
+    }
+
+    #[test]
+    fn shifted_frames_show_motion_in_proportion() {
+        let small = {
+            let mut detector = MotionDetector::default();
+            detector.update(&ramp(640, 360, 0));
+            detector.update(&ramp(640, 360, 20))
+        };
+        let large = {
+            let mut detector = MotionDetector::default();
+            detector.update(&ramp(640, 360, 0));
+            detector.update(&ramp(640, 360, 160))
+        };
+        assert!(small > 0.0);
+        assert!(large > small, "{} vs {}", large, small);
+        assert!(large <= MOTION_SMOOTHING);
+    }
+
+    #[test]
+    fn level_rises_and_decays_smoothly() {
+        let black = FrameData { data: vec![0; 64 * 36 * 3], ..ramp(64, 36, 0) };
+        let black = FrameData { format: VideoFormat::RGB, ..black };
+        let white = FrameData { data: vec![255; 64 * 36 * 3], ..black.clone() };
+        let mut detector = MotionDetector::default();
+
+        assert_eq!(detector.update(&black), 0.0);
+        assert!((detector.update(&white) - 0.4).abs() < 1e-12);
+        assert!((detector.update(&black) - 0.64).abs() < 1e-12);
+        // Each still frame keeps 60% of the level
+        assert!((detector.update(&black) - 0.384).abs() < 1e-12);
+
+        // Frames it can't read leave the level alone
+        let planar = FrameData { format: VideoFormat::I420, ..white };
+        assert!((detector.update(&planar) - 0.384).abs() < 1e-12);
+    }
+
+    #[test]
+    fn thumbnail_samples_a_fixed_grid() {
+        let thumbnail = luma_thumbnail(&ramp(1920, 1080, 0)).unwrap();
+        assert_eq!(thumbnail.len(), (GRID_WIDTH * GRID_HEIGHT) as usize);
+        assert_eq!(thumbnail[0], 0);
+        assert!(thumbnail[GRID_WIDTH as usize - 1] > 240);
+
+        let short = FrameData { data: vec![0; 10], ..ramp(64, 36, 0) };
+        assert!(luma_thumbnail(&short).is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::LazyLock;
+use utoipa::openapi::path::PathItem;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::OpenApi as Spec;
+use utoipa::{Modify, OpenApi};
+
+use crate::error::ErrorBody;
+use crate::events::{Event, StampedEvent};
+
+// Handlers carry their own #[utoipa::path] with the unversioned path; the
+// versioned copies are added in spec()
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Lumina adaptive night light API",
+        license(name = "MIT"),
+        description = "Every route is served under /api/v1 and, for older clients, without the prefix. \
+            Requests need the API token as a bearer token. Errors share one body: branch on `code`, which never changes once released."
+    ),
+    paths(
+        crate::get_ui,
+        crate::get_health,
+        crate::create_ticket,
+        crate::get_status,
+        crate::get_metrics,
+        crate::ws::events_socket,
+        crate::sse::event_stream,
+        crate::get_history,
+        crate::get_daily_report,
+        crate::get_weekly_report,
+        crate::list_sleep,
+        crate::log_sleep,
+        crate::get_sleep_insights,
+        crate::get_config,
+        crate::update_config,
+        crate::start_monitoring,
+        crate::stop_monitoring,
+        crate::set_override,
+        crate::clear_override,
+        crate::start_recording,
+        crate::stop_recording,
+        crate::list_profiles,
+        crate::get_profile,
+        crate::put_profile,
+        crate::delete_profile,
+        crate::activate_profile,
+        crate::list_rules,
+        crate::put_rule,
+        crate::delete_rule,
+        crate::get_preferences,
+        crate::reset_preferences,
+    ),
+    components(schemas(ErrorBody, Event, StampedEvent)),
+    modifiers(&BearerToken),
+    security(("api_token" = [])),
+    tags(
+        (name = "monitoring", description = "Starting, stopping and watching the screen analysis"),
+        (name = "config", description = "Night light settings and temporary overrides"),
+        (name = "profiles", description = "Named sets of temperature bounds and policy"),
+        (name = "rules", description = "Per-application behaviour"),
+        (name = "preferences", description = "The model learned from manual adjustments"),
+        (name = "recording", description = "Recording captured frames for later replay"),
+        (name = "history", description = "Downsampled history of analyses and temperatures"),
+        (name = "reports", description = "Blue-light exposure reports"),
+        (name = "sleep", description = "Sleep latency journal and model"),
+        (name = "events", description = "Live event streams"),
+        (name = "system", description = "Health and metrics"),
+    )
+)]
+struct ApiDoc;
+
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut Spec) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_token",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("The token in api-token in the config directory"))
+                    .build(),
+            ),
+        );
+    }
+}
+
+const VERSION_PREFIX: &str = "/api/v1";
+// Served only outside the versioned scope
+const UNVERSIONED_ONLY: [&str; 1] = ["/"];
+
+static SPEC: LazyLock<Spec> = LazyLock::new(|| {
+    let mut spec = ApiDoc::openapi();
+    let aliases = std::mem::take(&mut spec.paths.paths);
+
+    for (path, item) in aliases {
+        if UNVERSIONED_ONLY.contains(&path.as_str()) {
+            spec.paths.paths.insert(path, item);
+            continue;
+        }
+        spec.paths.paths.insert(format!("{}{}", VERSION_PREFIX, path), item.clone());
+        spec.paths.paths.insert(path, alias(item));
+    }
+    spec
+});
+
+// The unversioned aliases behave identically but need their own operation ids
+fn alias(mut item: PathItem) -> PathItem {
+    for operation in [
+        &mut item.get,
+        &mut item.put,
+        &mut item.post,
+        &mut item.delete,
+        &mut item.patch,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        operation.operation_id = operation.operation_id.take().map(|id| format!("{}_unversioned", id));
+        operation.description = Some(match operation.description.take() {
+            Some(description) => format!("{}\n\nAlias of the same route under {}.", description, VERSION_PREFIX),
+            None => format!("Alias of the same route under {}.", VERSION_PREFIX),
+        });
+    }
+    item
+}
+
+pub fn spec() -> &'static Spec {
+    &SPEC
+}
+
+// GET /api/v1/openapi.json
+pub async fn openapi_json() -> actix_web::HttpResponse {
+    actix_web::HttpResponse::Ok().json(spec())
+}
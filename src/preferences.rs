@@ -0,0 +1,319 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::clock::unix_millis;
+use crate::storage::{load_json, save_json};
+use crate::FrameAnalysis;
+
+// Oldest samples are dropped past this point so the model follows drifting habits
+const MAX_SAMPLES: usize = 500;
+// Below this the learned offset is ignored and the stock policy runs unchanged
+const MIN_SAMPLES_TO_APPLY: usize = 3;
+const RIDGE_LAMBDA: f64 = 1.0;
+const FEATURE_COUNT: usize = 6;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OverrideSample {
+    pub average_brightness: f64,
+    pub blue_intensity: f64,
+    pub ambient_light_level: f64,
+    pub hour_of_day: f64,
+    pub baseline_temperature: u32,
+    pub chosen_temperature: u32,
+    pub recorded_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ModelCoefficients {
+    pub intercept: f64,
+    pub brightness: f64,
+    pub blue_intensity: f64,
+    pub ambient_light_level: f64,
+    pub hour_sin: f64,
+    pub hour_cos: f64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PreferenceSnapshot {
+    pub sample_count: usize,
+    pub active: bool,
+    pub coefficients: Option<ModelCoefficients>,
+    pub paused: bool,
+    pub pause_remaining_secs: u64,
+    pub samples: Vec<OverrideSample>,
+}
+
+// Learns how far the user's manual choices sit from the stock policy.
+// The model is a ridge regression of (chosen - baseline) on the frame
+// analysis and a cyclic encoding of the local time of day.
+pub struct PreferenceLearner {
+    samples: Vec<OverrideSample>,
+    weights: Option<[f64; FEATURE_COUNT]>,
+    paused_until: Option<Instant>,
+    storage_path: PathBuf,
+}
+
+impl PreferenceLearner {
+    pub fn load(storage_path: PathBuf) -> Self {
+        let samples = match load_json::<Vec<OverrideSample>>(&storage_path) {
+            Ok(samples) => samples.unwrap_or_default(),
+            Err(e) => {
+                eprintln!("Ignoring unreadable preference history {}: {}", storage_path.display(), e);
+                Vec::new()
+            }
+        };
+
+        let mut learner = Self {
+            samples,
+            weights: None,
+            paused_until: None,
+            storage_path,
+        };
+        learner.fit();
+        learner
+    }
+
+    pub fn record_override(
+        &mut self,
+        analysis: &FrameAnalysis,
+        hour_of_day: f64,
+        baseline_temperature: u32,
+        chosen_temperature: u32,
+    ) {
+        self.samples.push(OverrideSample {
+            average_brightness: analysis.average_brightness,
+            blue_intensity: analysis.blue_intensity,
+            ambient_light_level: analysis.ambient_light_level,
+            hour_of_day,
+            baseline_temperature,
+            chosen_temperature,
+            recorded_at: unix_millis(),
+        });
+
+        if self.samples.len() > MAX_SAMPLES {
+            let excess = self.samples.len() - MAX_SAMPLES;
+            self.samples.drain(..excess);
+        }
+
+        self.fit();
+        self.save();
+    }
+
+    pub fn pause(&mut self, duration: Duration) {
+        self.paused_until = Some(Instant::now() + duration);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    // Shifts the stock policy's temperature by the learned offset for this context
+    pub fn personalize(&self, analysis: &FrameAnalysis, hour_of_day: f64, baseline_temperature: u32) -> u32 {
+        let Some(weights) = self.weights else {
+            return baseline_temperature;
+        };
+
+        let features = features(
+            analysis.average_brightness,
+            analysis.blue_intensity,
+            analysis.ambient_light_level,
+            hour_of_day,
+        );
+        let offset: f64 = weights.iter().zip(features.iter()).map(|(w, x)| w * x).sum();
+
+        (baseline_temperature as f64 + offset).round().clamp(1000.0, 10000.0) as u32
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.weights = None;
+        self.paused_until = None;
+        self.save();
+    }
+
+    pub fn snapshot(&self) -> PreferenceSnapshot {
+        let pause_remaining_secs = self
+            .paused_until
+            .map(|until| until.saturating_duration_since(Instant::now()).as_secs())
+            .unwrap_or(0);
+
+        PreferenceSnapshot {
+            sample_count: self.samples.len(),
+            active: self.weights.is_some(),
+            coefficients: self.weights.map(|w| ModelCoefficients {
+                intercept: w[0],
+                brightness: w[1],
+                blue_intensity: w[2],
+                ambient_light_level: w[3],
+                hour_sin: w[4],
+                hour_cos: w[5],
+            }),
+            paused: self.is_paused(),
+            pause_remaining_secs,
+            samples: self.samples.clone(),
+        }
+    }
+
+    fn fit(&mut self) {
+        if self.samples.len() < MIN_SAMPLES_TO_APPLY {
+            self.weights = None;
+            return;
+        }
+
+        // Normal equations (XᵀX + λI) w = Xᵀy
+        let mut xtx = [[0.0; FEATURE_COUNT]; FEATURE_COUNT];
+        let mut xty = [0.0; FEATURE_COUNT];
+
+        for sample in &self.samples {
+            let x = features(
+                sample.average_brightness,
+                sample.blue_intensity,
+                sample.ambient_light_level,
+                sample.hour_of_day,
+            );
+            let y = sample.chosen_temperature as f64 - sample.baseline_temperature as f64;
+
+            for i in 0..FEATURE_COUNT {
+                xty[i] += x[i] * y;
+                for j in 0..FEATURE_COUNT {
+                    xtx[i][j] += x[i] * x[j];
+                }
+            }
+        }
+
+        for (i, row) in xtx.iter_mut().enumerate() {
+            row[i] += RIDGE_LAMBDA;
+        }
+
+        self.weights = solve(xtx, xty);
+    }
+
+    fn save(&self) {
+        if let Err(e) = save_json(&self.storage_path, &self.samples) {
+            eprintln!("Failed to save preference history: {}", e);
+        }
+    }
+}
+
+fn features(brightness: f64, blue_intensity: f64, ambient_light_level: f64, hour_of_day: f64) -> [f64; FEATURE_COUNT] {
+    let angle = hour_of_day / 24.0 * std::f64::consts::TAU;
+    [
+        1.0,
+        brightness / 255.0,
+        blue_intensity / 255.0,
+        ambient_light_level,
+        angle.sin(),
+        angle.cos(),
+    ]
+}
+
+// Gaussian elimination with partial pivoting
+pub fn solve<const N: usize>(mut a: [[f64; N]; N], mut b: [f64; N]) -> Option<[f64; N]> {
+    for col in 0..N {
+        let pivot = (col..N)
+            .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let pivot_row = a[col];
+        for row in (col + 1)..N {
+            let factor = a[row][col] / pivot_row[col];
+            for (value, pivot_value) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * pivot_value;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.0; N];
+    for row in (0..N).rev() {
+        let tail: f64 = ((row + 1)..N).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - tail) / a[row][row];
+    }
+
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analysis(brightness: f64, blue_intensity: f64, ambient_light_level: f64) -> FrameAnalysis {
+        FrameAnalysis {
+            average_brightness: brightness,
+            blue_intensity,
+            ambient_light_level,
+            ambient_source: "estimated",
+            ambient_lux: None,
+            motion_level: 0.0,
+            timestamp: 0,
+            frame_size: 0,
+        }
+    }
+
+    #[test]
+    fn solve_recovers_known_solution() {
+        // x = 1, y = -2, z = 3; the zero in the corner forces a pivot swap
+        let a = [[0.0, 2.0, 1.0], [1.0, 1.0, 1.0], [2.0, -1.0, 3.0]];
+        let b = [-1.0, 2.0, 13.0];
+        let x = solve(a, b).expect("system is regular");
+        for (got, want) in x.iter().zip([1.0, -2.0, 3.0]) {
+            assert!((got - want).abs() < 1e-9, "{:?}", x);
+        }
+    }
+
+    #[test]
+    fn solve_rejects_singular_system() {
+        let a = [[1.0, 2.0], [2.0, 4.0]];
+        assert!(solve(a, [3.0, 6.0]).is_none());
+        assert!(solve([[0.0; 3]; 3], [0.0; 3]).is_none());
+    }
+
+    #[test]
+    fn fit_recovers_planted_offset() {
+        let mut learner = PreferenceLearner {
+            samples: Vec::new(),
+            weights: None,
+            paused_until: None,
+            storage_path: PathBuf::new(),
+        };
+        // Users here always want it 400K warmer on bright screens, scaling down to 0 on black ones
+        for i in 0..200 {
+            let brightness = (i * 37 % 256) as f64;
+            let hour = (i % 24) as f64;
+            let offset = -400.0 * brightness / 255.0;
+            learner.samples.push(OverrideSample {
+                average_brightness: brightness,
+                blue_intensity: (i * 11 % 256) as f64,
+                ambient_light_level: (i % 10) as f64 / 10.0,
+                hour_of_day: hour,
+                baseline_temperature: 5000,
+                chosen_temperature: (5000.0 + offset) as u32,
+                recorded_at: 0,
+            });
+        }
+        learner.fit();
+
+        let personalized = learner.personalize(&analysis(255.0, 128.0, 0.5), 21.0, 5000);
+        assert!((personalized as i64 - 4600).abs() <= 15, "{}", personalized);
+        let personalized = learner.personalize(&analysis(0.0, 128.0, 0.5), 9.0, 5000);
+        assert!((personalized as i64 - 5000).abs() <= 15, "{}", personalized);
+    }
+
+    #[test]
+    fn too_few_samples_leave_policy_unchanged() {
+        let mut learner = PreferenceLearner {
+            samples: Vec::new(),
+            weights: None,
+            paused_until: None,
+            storage_path: PathBuf::new(),
+        };
+        learner.fit();
+        assert_eq!(learner.personalize(&analysis(100.0, 100.0, 0.5), 12.0, 4500), 4500);
+    }
+}
@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::error::FieldError;
+use crate::storage::{load_json, save_json};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TemperaturePolicy {
+    // Follow the screen analysis within the profile's bounds
+    Adaptive,
+    // Hold `temperature` and ignore the analysis entirely
+    Fixed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Profile {
+    pub min_temperature: u32,
+    pub max_temperature: u32,
+    pub policy: TemperaturePolicy,
+    pub temperature: u32,
+    pub smoothing: f64, // 0.0 jumps straight to the target, values near 1.0 move slowly
+    pub brightness: Option<u32>, // Screen brightness in percent, left alone when unset
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct NamedProfile {
+    pub name: String,
+    pub active: bool,
+    #[serde(flatten)]
+    pub profile: Profile,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StoredProfiles {
+    active: Option<String>,
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Profile {
+    pub fn validate(&self) -> Result<(), FieldError> {
+        if self.min_temperature < 1000 || self.max_temperature > 10000 {
+            let field = if self.min_temperature < 1000 { "min_temperature" } else { "max_temperature" };
+            return Err(FieldError::new(field, "Temperature bounds must be between 1000K and 10000K"));
+        }
+        if self.min_temperature > self.max_temperature {
+            return Err(FieldError::new("min_temperature", "min_temperature must not exceed max_temperature"));
+        }
+        if self.temperature < self.min_temperature || self.temperature > self.max_temperature {
+            return Err(FieldError::new("temperature", "temperature must lie within the profile's bounds"));
+        }
+        if !(0.0..1.0).contains(&self.smoothing) {
+            return Err(FieldError::new("smoothing", "smoothing must be in the range [0.0, 1.0)"));
+        }
+        if self.brightness.is_some_and(|brightness| brightness > 100) {
+            return Err(FieldError::new("brightness", "brightness must be a percentage between 0 and 100"));
+        }
+        Ok(())
+    }
+}
+
+fn builtin_profiles() -> BTreeMap<String, Profile> {
+    BTreeMap::from([
+        ("Reading".to_string(), Profile {
+            min_temperature: 2700,
+            max_temperature: 4500,
+            policy: TemperaturePolicy::Adaptive,
+            temperature: 3500,
+            smoothing: 0.8,
+            brightness: Some(60),
+        }),
+        ("Gaming".to_string(), Profile {
+            min_temperature: 4500,
+            max_temperature: 6500,
+            policy: TemperaturePolicy::Adaptive,
+            temperature: 5500,
+            smoothing: 0.5,
+            brightness: None,
+        }),
+        ("Movie".to_string(), Profile {
+            min_temperature: 3400,
+            max_temperature: 5500,
+            policy: TemperaturePolicy::Adaptive,
+            temperature: 4500,
+            smoothing: 0.95,
+            brightness: None,
+        }),
+        ("Design work".to_string(), Profile {
+            min_temperature: 6500,
+            max_temperature: 6500,
+            policy: TemperaturePolicy::Fixed,
+            temperature: 6500,
+            smoothing: 0.0,
+            brightness: None,
+        }),
+    ])
+}
+
+pub struct ProfileStore {
+    stored: StoredProfiles,
+    storage_path: PathBuf,
+}
+
+impl ProfileStore {
+    // Seeds the built-in profiles the first time, then trusts the file
+    pub fn load(storage_path: PathBuf) -> Self {
+        let stored = match load_json::<StoredProfiles>(&storage_path) {
+            Ok(Some(stored)) => stored,
+            Ok(None) => StoredProfiles {
+                active: None,
+                profiles: builtin_profiles(),
+            },
+            Err(e) => {
+                eprintln!("Ignoring unreadable profiles {}: {}", storage_path.display(), e);
+                StoredProfiles {
+                    active: None,
+                    profiles: builtin_profiles(),
+                }
+            }
+        };
+
+        Self { stored, storage_path }
+    }
+
+    pub fn list(&self) -> Vec<NamedProfile> {
+        self.stored
+            .profiles
+            .keys()
+            .filter_map(|name| self.get(name))
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<NamedProfile> {
+        self.stored.profiles.get(name).map(|profile| NamedProfile {
+            name: name.to_string(),
+            active: self.stored.active.as_deref() == Some(name),
+            profile: profile.clone(),
+        })
+    }
+
+    pub fn active(&self) -> Option<NamedProfile> {
+        self.stored.active.as_deref().and_then(|name| self.get(name))
+    }
+
+    // Returns true when a new profile was created rather than replaced
+    pub fn upsert(&mut self, name: &str, profile: Profile) -> std::io::Result<bool> {
+        let created = self.stored.profiles.insert(name.to_string(), profile).is_none();
+        self.save()?;
+        Ok(created)
+    }
+
+    pub fn remove(&mut self, name: &str) -> std::io::Result<bool> {
+        if self.stored.profiles.remove(name).is_none() {
+            return Ok(false);
+        }
+        if self.stored.active.as_deref() == Some(name) {
+            self.stored.active = None;
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn set_active(&mut self, name: &str) -> std::io::Result<()> {
+        self.stored.active = Some(name.to_string());
+        self.save()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        save_json(&self.storage_path, &self.stored)
+    }
+}
@@ -0,0 +1,392 @@
+use pipewire::spa::param::video::VideoFormat;
+use serde::Serialize;
+use utoipa::ToSchema;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::capture::{
+    bytes_per_pixel, format_name, CaptureSettings, FrameData, FrameReceiver, FrameSource, SharedStreamInfo, StartFuture,
+    StreamInfo,
+};
+
+// Recording layout: MAGIC, then one record per frame of
+//   u64 microseconds since the first frame, u32 width, u32 height,
+//   u8 format code, u32 payload length, payload
+// with every integer little-endian.
+const MAGIC: &[u8; 8] = b"LUMREC1\n";
+// Frames are thinned out to at most this width before they are written
+const RECORD_MAX_WIDTH: u32 = 320;
+// Refuse payloads no capture could have produced rather than allocating them
+const MAX_PAYLOAD_LEN: usize = 64 * 1024 * 1024;
+
+// Format codes are positions in this table plus one; 0 is never written
+const FORMAT_CODES: [VideoFormat; 6] = [
+    VideoFormat::RGB,
+    VideoFormat::RGBA,
+    VideoFormat::RGBx,
+    VideoFormat::BGRx,
+    VideoFormat::YUY2,
+    VideoFormat::I420,
+];
+
+fn format_code(format: VideoFormat) -> u8 {
+    FORMAT_CODES
+        .iter()
+        .position(|&known| known == format)
+        .map_or(0, |index| index as u8 + 1)
+}
+
+fn format_from_code(code: u8) -> Option<VideoFormat> {
+    FORMAT_CODES.get((code as usize).checked_sub(1)?).copied()
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RecordingSummary {
+    #[schema(value_type = String)]
+    pub path: PathBuf,
+    pub frames: u64,
+    pub duration_secs: f64,
+}
+
+// Appends captured frames to a recording until finished
+pub struct FrameRecorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    started_at: Option<Instant>,
+    last_offset: Duration,
+    frames: u64,
+}
+
+impl FrameRecorder {
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writer.write_all(MAGIC)?;
+
+        Ok(Self {
+            writer,
+            path,
+            started_at: None,
+            last_offset: Duration::ZERO,
+            frames: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, frame: &FrameData) -> io::Result<()> {
+        let code = format_code(frame.format);
+        if code == 0 {
+            return Ok(());
+        }
+
+        let started_at = *self.started_at.get_or_insert(frame.timestamp);
+        let offset = frame.timestamp.saturating_duration_since(started_at);
+        let (width, height, data) = downscale(frame);
+
+        self.writer.write_all(&(offset.as_micros() as u64).to_le_bytes())?;
+        self.writer.write_all(&width.to_le_bytes())?;
+        self.writer.write_all(&height.to_le_bytes())?;
+        self.writer.write_all(&[code])?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&data)?;
+
+        self.last_offset = offset;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<RecordingSummary> {
+        self.writer.flush()?;
+        Ok(RecordingSummary {
+            path: self.path,
+            frames: self.frames,
+            duration_secs: self.last_offset.as_secs_f64(),
+        })
+    }
+}
+
+// Nearest-neighbour thinning for the packed formats. Planar I420 and frames
+// that are already small enough are kept as they are.
+fn downscale(frame: &FrameData) -> (u32, u32, Vec<u8>) {
+    let step = frame.width.div_ceil(RECORD_MAX_WIDTH) as usize;
+    let (unit_bytes, unit_pixels) = match frame.format {
+        VideoFormat::YUY2 => (4, 2), // Y0 U Y1 V covers two pixels
+        VideoFormat::I420 => return (frame.width, frame.height, frame.data.clone()),
+        format => (bytes_per_pixel(format) as usize, 1),
+    };
+
+    let units_per_row = frame.width as usize / unit_pixels;
+    let row_bytes = units_per_row * unit_bytes;
+    let rows = frame.height as usize;
+    if step <= 1 || row_bytes == 0 || frame.data.len() < row_bytes * rows {
+        return (frame.width, frame.height, frame.data.clone());
+    }
+
+    let mut data = Vec::with_capacity(frame.data.len() / (step * step));
+    let mut height = 0;
+    for row in frame.data.chunks_exact(row_bytes).take(rows).step_by(step) {
+        for unit in row.chunks_exact(unit_bytes).step_by(step) {
+            data.extend_from_slice(unit);
+        }
+        height += 1;
+    }
+    let width = units_per_row.div_ceil(step) * unit_pixels;
+
+    (width as u32, height, data)
+}
+
+struct RecordedFrame {
+    offset: Duration,
+    width: u32,
+    height: u32,
+    format: VideoFormat,
+    data: Vec<u8>,
+}
+
+// Returns None at a clean end of file
+fn read_frame(reader: &mut impl Read) -> io::Result<Option<RecordedFrame>> {
+    let mut header = [0u8; 21];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let offset = u64::from_le_bytes(header[0..8].try_into().unwrap());
+    let width = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    let height = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    let format = format_from_code(header[16])
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown frame format"))?;
+    let len = u32::from_le_bytes(header[17..21].try_into().unwrap()) as usize;
+    if len > MAX_PAYLOAD_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame payload too large"));
+    }
+
+    let mut data = vec![0u8; len];
+    reader.read_exact(&mut data)?;
+
+    Ok(Some(RecordedFrame {
+        offset: Duration::from_micros(offset),
+        width,
+        height,
+        format,
+        data,
+    }))
+}
+
+// Plays a recording back in place of the screen capture. LUMINA_REPLAY names
+// the file and LUMINA_REPLAY_SPEED speeds it up (2 plays twice as fast).
+// Frames arrive through the same newest-only channel as live capture, so at
+// high speeds the analysis skips frames just as it would live.
+pub struct ReplaySource {
+    path: PathBuf,
+    speed: f64,
+    stream_info: SharedStreamInfo,
+}
+
+impl ReplaySource {
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("LUMINA_REPLAY").filter(|path| !path.is_empty())?;
+        let speed = std::env::var("LUMINA_REPLAY_SPEED")
+            .ok()
+            .and_then(|speed| speed.parse::<f64>().ok())
+            .filter(|speed| speed.is_finite() && *speed > 0.0)
+            .unwrap_or(1.0);
+
+        Some(Self { path: PathBuf::from(path), speed, stream_info: SharedStreamInfo::default() })
+    }
+
+    // Checks the file up front so a bad path fails the start request
+    fn open(&self) -> io::Result<FrameReceiver> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a frame recording"));
+        }
+
+        let (frame_sender, frame_receiver) = watch::channel(None);
+        let speed = self.speed;
+        let path = self.path.clone();
+        let stream_info = self.stream_info.clone();
+        *stream_info.lock().unwrap() = None;
+
+        thread::spawn(move || {
+            let started_at = Instant::now();
+            let mut sequence = 0u64;
+            loop {
+                let recorded = match read_frame(&mut reader) {
+                    Ok(Some(recorded)) => recorded,
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("Replay of {} stopped: {}", path.display(), e);
+                        break;
+                    }
+                };
+
+                let due = started_at + recorded.offset.div_f64(speed);
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+
+                // A recording carries no negotiated rate, so report what arrives
+                sequence += 1;
+                let fps = sequence as f64 / started_at.elapsed().as_secs_f64().max(f64::EPSILON);
+                *stream_info.lock().unwrap() = Some(StreamInfo {
+                    format: format_name(recorded.format),
+                    width: recorded.width,
+                    height: recorded.height,
+                    framerate: fps,
+                    variable_framerate: true,
+                    delivered_fps: fps,
+                    output: None,
+                });
+
+                let frame = FrameData {
+                    data: recorded.data,
+                    width: recorded.width,
+                    height: recorded.height,
+                    format: recorded.format,
+                    timestamp: Instant::now(),
+                    sequence,
+                };
+
+                // Monitoring was stopped
+                if frame_sender.send(Some(Arc::new(frame))).is_err() {
+                    return;
+                }
+            }
+            println!("Replay of {} finished after {} frames", path.display(), sequence);
+        });
+
+        Ok(frame_receiver)
+    }
+}
+
+impl FrameSource for ReplaySource {
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    // Recordings keep the size and rate they were captured with
+    fn start<'a>(&'a self, _settings: &'a CaptureSettings) -> StartFuture<'a> {
+        let opened = self
+            .open()
+            .map_err(|e| format!("{}: {}", self.path.display(), e).into());
+        Box::pin(async move { opened })
+    }
+
+    fn stop(&self) {
+        *self.stream_info.lock().unwrap() = None;
+    }
+
+    fn metadata(&self) -> Option<StreamInfo> {
+        self.stream_info.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each byte tells where it came from, so thinning can be checked exactly
+    fn frame(width: u32, height: u32, format: VideoFormat, len: usize, timestamp: Instant) -> FrameData {
+        let data = (0..len).map(|i| (i * 7 % 251) as u8).collect();
+        FrameData { data, width, height, format, timestamp, sequence: 0 }
+    }
+
+    fn packed(width: u32, height: u32, format: VideoFormat, timestamp: Instant) -> FrameData {
+        let len = (width * height * bytes_per_pixel(format)) as usize;
+        frame(width, height, format, len, timestamp)
+    }
+
+    #[test]
+    fn recordings_read_back_as_written() {
+        let path = crate::storage::test_dir("recording-roundtrip").join("frames.lrec");
+        let start = Instant::now();
+        let frames = [
+            packed(4, 3, VideoFormat::RGB, start),
+            packed(5, 2, VideoFormat::BGRx, start + Duration::from_millis(40)),
+            packed(6, 2, VideoFormat::YUY2, start + Duration::from_micros(80_123)),
+            // Y plane, then quarter-size U and V planes
+            frame(4, 4, VideoFormat::I420, 16 + 4 + 4, start + Duration::from_secs(2)),
+        ];
+
+        let mut recorder = FrameRecorder::create(path.clone()).unwrap();
+        for frame in &frames {
+            recorder.record(frame).unwrap();
+        }
+        // Formats without a code are skipped
+        recorder.record(&packed(2, 2, VideoFormat::BGRA, start + Duration::from_secs(3))).unwrap();
+        let summary = recorder.finish().unwrap();
+        assert_eq!(summary.frames, 4);
+        assert_eq!(summary.duration_secs, 2.0);
+
+        let mut reader = BufReader::new(File::open(&path).unwrap());
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).unwrap();
+        assert_eq!(&magic, MAGIC);
+
+        let offsets = [0, 40_000, 80_123, 2_000_000];
+        for (frame, offset) in frames.iter().zip(offsets) {
+            let recorded = read_frame(&mut reader).unwrap().unwrap();
+            assert_eq!(recorded.offset, Duration::from_micros(offset));
+            assert_eq!((recorded.width, recorded.height, recorded.format), (frame.width, frame.height, frame.format));
+            assert_eq!(recorded.data, frame.data);
+        }
+        assert!(read_frame(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn wide_frames_are_thinned_before_writing() {
+        let path = crate::storage::test_dir("recording-thinned").join("frames.lrec");
+        let wide = packed(1000, 10, VideoFormat::BGRx, Instant::now());
+        let mut recorder = FrameRecorder::create(path.clone()).unwrap();
+        recorder.record(&wide).unwrap();
+        recorder.finish().unwrap();
+
+        let mut reader = BufReader::new(File::open(&path).unwrap());
+        reader.read_exact(&mut [0u8; 8]).unwrap();
+        let recorded = read_frame(&mut reader).unwrap().unwrap();
+        // Every fourth pixel of every fourth row, rows packed without padding
+        assert_eq!((recorded.width, recorded.height), (250, 3));
+        assert_eq!(recorded.data.len(), 250 * 4 * 3);
+        let stride = 1000 * 4;
+        let (x, y) = (7, 2);
+        let source = (y * 4) * stride + (x * 4) * 4;
+        let written = (y * 250 + x) * 4;
+        assert_eq!(recorded.data[written..written + 4], wide.data[source..source + 4]);
+    }
+
+    #[test]
+    fn downscale_sizes() {
+        let now = Instant::now();
+        let cases = [
+            (packed(320, 4, VideoFormat::RGBx, now), (320, 4, 320 * 4 * 4)),
+            (packed(321, 5, VideoFormat::RGB, now), (161, 3, 161 * 3 * 3)),
+            (packed(1920, 1080, VideoFormat::BGRx, now), (320, 180, 320 * 4 * 180)),
+            // Two pixels per four bytes; thinning keeps whole pairs
+            (packed(1280, 4, VideoFormat::YUY2, now), (320, 1, 160 * 4)),
+            (frame(1280, 2, VideoFormat::I420, 1280 * 3, now), (1280, 2, 1280 * 3)),
+        ];
+        for (frame, (width, height, len)) in cases {
+            let (w, h, data) = downscale(&frame);
+            assert_eq!((w, h, data.len()), (width, height, len), "{:?} {}x{}", frame.format, frame.width, frame.height);
+        }
+
+        // Short buffers are passed through rather than misread
+        let short = frame(1000, 10, VideoFormat::RGBx, 100, now);
+        assert_eq!(downscale(&short).2, short.data);
+    }
+}
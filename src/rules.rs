@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::error::FieldError;
+use crate::storage::{load_json, save_json};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RuleAction {
+    // Turn night light off while the application has focus
+    Disable,
+    // Never let adaptation go above this temperature
+    CapTemperature { max_temperature: u32 },
+    // Use a named profile while the application has focus
+    SwitchProfile { profile: String },
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AppRule {
+    pub app: String,
+    #[serde(flatten)]
+    pub action: RuleAction,
+}
+
+impl RuleAction {
+    pub fn validate(&self) -> Result<(), FieldError> {
+        match self {
+            RuleAction::Disable => Ok(()),
+            RuleAction::CapTemperature { max_temperature } => {
+                if (1000..=10000).contains(max_temperature) {
+                    Ok(())
+                } else {
+                    Err(FieldError::new("max_temperature", "max_temperature must be between 1000K and 10000K"))
+                }
+            },
+            RuleAction::SwitchProfile { profile } => {
+                if profile.trim().is_empty() {
+                    Err(FieldError::new("profile", "profile must not be empty"))
+                } else {
+                    Ok(())
+                }
+            },
+        }
+    }
+}
+
+// Rules keyed by lowercase application identifier
+pub struct RuleStore {
+    rules: BTreeMap<String, RuleAction>,
+    storage_path: PathBuf,
+}
+
+impl RuleStore {
+    pub fn load(storage_path: PathBuf) -> Self {
+        let rules = match load_json::<BTreeMap<String, RuleAction>>(&storage_path) {
+            Ok(rules) => rules.unwrap_or_default(),
+            Err(e) => {
+                eprintln!("Ignoring unreadable rules {}: {}", storage_path.display(), e);
+                BTreeMap::new()
+            }
+        };
+
+        Self { rules, storage_path }
+    }
+
+    pub fn list(&self) -> Vec<AppRule> {
+        self.rules
+            .iter()
+            .map(|(app, action)| AppRule { app: app.clone(), action: action.clone() })
+            .collect()
+    }
+
+    // Returns true when a new rule was created rather than replaced
+    pub fn upsert(&mut self, app: &str, action: RuleAction) -> std::io::Result<bool> {
+        let created = self.rules.insert(app.to_lowercase(), action).is_none();
+        self.save()?;
+        Ok(created)
+    }
+
+    pub fn remove(&mut self, app: &str) -> std::io::Result<bool> {
+        if self.rules.remove(&app.to_lowercase()).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    // Matches "evince" against "evince", "Evince" and "org.gnome.Evince"
+    pub fn rule_for(&self, app_id: &str) -> Option<&RuleAction> {
+        let app_id = app_id.to_lowercase();
+        self.rules.get(&app_id).or_else(|| {
+            let (_, last_segment) = app_id.rsplit_once('.')?;
+            self.rules.get(last_segment)
+        })
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        save_json(&self.storage_path, &self.rules)
+    }
+}
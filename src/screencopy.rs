@@ -0,0 +1,553 @@
+use pipewire::spa::param::video::VideoFormat;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tokio::sync::watch;
+use wayland_client::globals::{registry_queue_init, GlobalList, GlobalListContents};
+use wayland_client::protocol::{wl_buffer, wl_output, wl_registry, wl_shm, wl_shm_pool};
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum};
+use wayland_protocols::ext::image_capture_source::v1::client::{
+    ext_image_capture_source_v1, ext_output_image_capture_source_manager_v1,
+};
+use wayland_protocols::ext::image_copy_capture::v1::client::{
+    ext_image_copy_capture_frame_v1, ext_image_copy_capture_manager_v1, ext_image_copy_capture_session_v1,
+};
+use wayland_protocols_wlr::screencopy::v1::client::{zwlr_screencopy_frame_v1, zwlr_screencopy_manager_v1};
+
+use crate::capture::{
+    copy_thinned, format_name, thinning_step, CaptureError, CaptureSettings, FrameData, FrameReceiver, FrameSource,
+    SharedStreamInfo, StartFuture, StreamInfo,
+};
+
+use ext_image_copy_capture_frame_v1::ExtImageCopyCaptureFrameV1 as ExtFrame;
+use ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1 as ExtCopyManager;
+use ext_image_copy_capture_session_v1::ExtImageCopyCaptureSessionV1 as ExtSession;
+use ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1 as ExtOutputSourceManager;
+use zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1 as WlrFrame;
+use zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1 as WlrManager;
+
+// Captures an output straight from a wlroots-style compositor (sway,
+// Hyprland, river, ...) without xdg-desktop-portal-wlr in between. Uses
+// ext-image-copy-capture when the compositor has it and falls back to
+// wlr-screencopy. Only one output is captured: LUMINA_CAPTURE_OUTPUT picks
+// it by name (e.g. "DP-1"), otherwise it is the first one, and /status
+// reports which it is under capture_stream.output.
+#[derive(Default)]
+pub struct ScreencopySource {
+    stream_info: SharedStreamInfo,
+}
+
+impl ScreencopySource {
+    // Only worth choosing automatically when the compositor speaks one of
+    // the protocols
+    pub fn detect() -> Option<Self> {
+        std::env::var_os("WAYLAND_DISPLAY")?;
+        let conn = Connection::connect_to_env().ok()?;
+        let (globals, _queue) = registry_queue_init::<State>(&conn).ok()?;
+        let supported = globals.contents().with_list(|list| {
+            list.iter().any(|global| {
+                global.interface == "zwlr_screencopy_manager_v1" || global.interface == "ext_image_copy_capture_manager_v1"
+            })
+        });
+        supported.then(Self::default)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Pending,
+    Ready,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BufferSpec {
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+struct State {
+    output_names: Vec<(wl_output::WlOutput, String)>,
+    // wlr-screencopy: the compositor dictates the buffer, stride included
+    offered: Option<BufferSpec>,
+    buffer_done: bool,
+    y_invert: bool,
+    // ext-image-copy-capture: the session lists constraints, we pick the stride
+    session_size: Option<(u32, u32)>,
+    session_formats: Vec<wl_shm::Format>,
+    session_done: bool,
+    session_stopped: bool,
+    outcome: Outcome,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            output_names: Vec::new(),
+            offered: None,
+            buffer_done: false,
+            y_invert: false,
+            session_size: None,
+            session_formats: Vec::new(),
+            session_done: false,
+            session_stopped: false,
+            outcome: Outcome::Pending,
+        }
+    }
+}
+
+// How the 32-bit wl_shm formats lay out in memory on little-endian hosts
+fn video_format(format: wl_shm::Format) -> Option<VideoFormat> {
+    match format {
+        wl_shm::Format::Xrgb8888 | wl_shm::Format::Argb8888 => Some(VideoFormat::BGRx),
+        wl_shm::Format::Xbgr8888 | wl_shm::Format::Abgr8888 => Some(VideoFormat::RGBx),
+        _ => None,
+    }
+}
+
+// A wl_shm buffer backed by a memfd mapped into our address space
+struct ShmBuffer {
+    spec: BufferSpec,
+    addr: *mut u8,
+    len: usize,
+    _fd: OwnedFd,
+    pool: wl_shm_pool::WlShmPool,
+    buffer: wl_buffer::WlBuffer,
+}
+
+// The mapping is only touched from the capture thread
+unsafe impl Send for ShmBuffer {}
+
+impl ShmBuffer {
+    fn new(shm: &wl_shm::WlShm, spec: BufferSpec, qh: &QueueHandle<State>) -> Result<Self, CaptureError> {
+        let len = spec.stride as usize * spec.height as usize;
+        let fd = unsafe { libc::memfd_create(c"lumina-screencopy".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let pool = shm.create_pool(fd.as_fd(), len as i32, qh, ());
+        let buffer = pool.create_buffer(
+            0,
+            spec.width as i32,
+            spec.height as i32,
+            spec.stride as i32,
+            spec.format,
+            qh,
+            (),
+        );
+
+        Ok(Self { spec, addr: addr as *mut u8, len, _fd: fd, pool, buffer })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.addr, self.len) }
+    }
+}
+
+impl Drop for ShmBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        self.pool.destroy();
+        unsafe { libc::munmap(self.addr as *mut libc::c_void, self.len) };
+    }
+}
+
+enum Protocol {
+    Ext { session: ExtSession },
+    Wlr { manager: WlrManager, output: wl_output::WlOutput },
+}
+
+struct OutputCapture {
+    queue: EventQueue<State>,
+    qh: QueueHandle<State>,
+    state: State,
+    shm: wl_shm::WlShm,
+    protocol: Protocol,
+    buffer: Option<ShmBuffer>,
+    output_name: String,
+    other_outputs: usize,
+    // Keeps the connection alive for as long as the capture runs
+    _conn: Connection,
+}
+
+impl OutputCapture {
+    fn open() -> Result<Self, CaptureError> {
+        let conn = Connection::connect_to_env()?;
+        let (globals, mut queue) = registry_queue_init::<State>(&conn)?;
+        let qh = queue.handle();
+        let mut state = State::new();
+
+        let shm: wl_shm::WlShm = globals.bind(&qh, 1..=1, ())?;
+        let outputs = bind_outputs(&globals, &qh);
+        // Output names arrive as events on the freshly bound outputs
+        queue.roundtrip(&mut state)?;
+
+        let wanted = std::env::var("LUMINA_CAPTURE_OUTPUT").ok();
+        let (output, output_name) = match &wanted {
+            Some(wanted) => state
+                .output_names
+                .iter()
+                .find(|(_, name)| name == wanted)
+                .cloned()
+                .ok_or_else(|| {
+                    let names: Vec<&str> = state.output_names.iter().map(|(_, name)| name.as_str()).collect();
+                    format!("no output named '{}' (have: {})", wanted, names.join(", "))
+                })?,
+            None => {
+                let output = outputs.first().ok_or("compositor has no outputs")?.clone();
+                let name = state
+                    .output_names
+                    .iter()
+                    .find(|(candidate, _)| *candidate == output)
+                    .map_or_else(|| "unnamed".to_string(), |(_, name)| name.clone());
+                (output, name)
+            },
+        };
+
+        let ext_managers = (
+            globals.bind::<ExtOutputSourceManager, _, _>(&qh, 1..=1, ()),
+            globals.bind::<ExtCopyManager, _, _>(&qh, 1..=1, ()),
+        );
+        let protocol = match ext_managers {
+            (Ok(source_manager), Ok(copy_manager)) => {
+                let source = source_manager.create_source(&output, &qh, ());
+                let session = copy_manager.create_session(
+                    &source,
+                    ext_image_copy_capture_manager_v1::Options::empty(),
+                    &qh,
+                    (),
+                );
+                // The session outlives the source object it was made from
+                source.destroy();
+                while !state.session_done && !state.session_stopped {
+                    queue.blocking_dispatch(&mut state)?;
+                }
+                Protocol::Ext { session }
+            },
+            _ => {
+                let manager: WlrManager = globals
+                    .bind(&qh, 1..=3, ())
+                    .map_err(|_| "compositor supports neither ext-image-copy-capture nor wlr-screencopy")?;
+                Protocol::Wlr { manager, output }
+            },
+        };
+
+        Ok(Self {
+            queue,
+            qh,
+            state,
+            shm,
+            protocol,
+            buffer: None,
+            output_name,
+            other_outputs: outputs.len().saturating_sub(1),
+            _conn: conn,
+        })
+    }
+
+    fn protocol_name(&self) -> &'static str {
+        match self.protocol {
+            Protocol::Ext { .. } => "ext-image-copy-capture",
+            Protocol::Wlr { .. } => "wlr-screencopy",
+        }
+    }
+
+    // Reuses the buffer until the compositor asks for a different one
+    fn buffer_for(&mut self, spec: BufferSpec) -> Result<&ShmBuffer, CaptureError> {
+        if self.buffer.as_ref().is_none_or(|buffer| buffer.spec != spec) {
+            self.buffer = None;
+            self.buffer = Some(ShmBuffer::new(&self.shm, spec, &self.qh)?);
+        }
+        Ok(self.buffer.as_ref().unwrap())
+    }
+
+    fn wait_for_outcome(&mut self) -> Result<(), CaptureError> {
+        while self.state.outcome == Outcome::Pending {
+            self.queue.blocking_dispatch(&mut self.state)?;
+        }
+        match self.state.outcome {
+            Outcome::Ready => Ok(()),
+            _ => Err("compositor failed to copy the frame".into()),
+        }
+    }
+
+    // Returns the copied buffer's contents, its layout and whether it is
+    // stored bottom-up
+    fn capture(&mut self) -> Result<(BufferSpec, bool), CaptureError> {
+        self.state.outcome = Outcome::Pending;
+        match &self.protocol {
+            Protocol::Ext { session } => {
+                let session = session.clone();
+                if self.state.session_stopped {
+                    return Err("capture session was stopped by the compositor".into());
+                }
+                let (width, height) = self.state.session_size.ok_or("capture session reported no buffer size")?;
+                let format = [
+                    wl_shm::Format::Xrgb8888,
+                    wl_shm::Format::Argb8888,
+                    wl_shm::Format::Xbgr8888,
+                    wl_shm::Format::Abgr8888,
+                ]
+                .into_iter()
+                .find(|format| self.state.session_formats.contains(format))
+                .ok_or("capture session offers no 32-bit shm format")?;
+                let spec = BufferSpec { format, width, height, stride: width * 4 };
+
+                let buffer = self.buffer_for(spec)?.buffer.clone();
+                let frame = session.create_frame(&self.qh, ());
+                frame.attach_buffer(&buffer);
+                frame.damage_buffer(0, 0, width as i32, height as i32);
+                frame.capture();
+                let result = self.wait_for_outcome();
+                frame.destroy();
+                result?;
+                Ok((spec, false))
+            },
+            Protocol::Wlr { manager, output } => {
+                self.state.offered = None;
+                self.state.buffer_done = false;
+                self.state.y_invert = false;
+
+                let frame = manager.capture_output(0, output, &self.qh, ());
+                // Version 3 announces every buffer type before buffer_done;
+                // older versions only ever offer the one shm buffer
+                let announces_done = frame.version() >= 3;
+                while self.state.outcome == Outcome::Pending
+                    && !(self.state.buffer_done || (!announces_done && self.state.offered.is_some()))
+                {
+                    self.queue.blocking_dispatch(&mut self.state)?;
+                }
+                let Some(spec) = self.state.offered else {
+                    frame.destroy();
+                    return Err("compositor offered no shm buffer".into());
+                };
+
+                let buffer = self.buffer_for(spec)?.buffer.clone();
+                frame.copy(&buffer);
+                let result = self.wait_for_outcome();
+                frame.destroy();
+                result?;
+                Ok((spec, self.state.y_invert))
+            },
+        }
+    }
+}
+
+fn bind_outputs(globals: &GlobalList, qh: &QueueHandle<State>) -> Vec<wl_output::WlOutput> {
+    let outputs = globals.contents().with_list(|list| {
+        list.iter()
+            .filter(|global| global.interface == "wl_output")
+            .map(|global| (global.name, global.version))
+            .collect::<Vec<_>>()
+    });
+    outputs
+        .into_iter()
+        .map(|(name, version)| globals.registry().bind::<wl_output::WlOutput, _, _>(name, version.min(4), qh, ()))
+        .collect()
+}
+
+fn start_output_capture(settings: &CaptureSettings, stream_info: SharedStreamInfo) -> Result<FrameReceiver, CaptureError> {
+    let mut capture = OutputCapture::open()?;
+    println!("Capturing output {} with {}", capture.output_name, capture.protocol_name());
+    if capture.other_outputs > 0 && std::env::var_os("LUMINA_CAPTURE_OUTPUT").is_none() {
+        println!("Ignoring {} other output(s); set LUMINA_CAPTURE_OUTPUT to pick another", capture.other_outputs);
+    }
+
+    let interval = settings.poll_interval();
+    let framerate = 1.0 / interval.as_secs_f64();
+    let settings = settings.clone();
+    let (frame_sender, frame_receiver) = watch::channel(None);
+
+    thread::spawn(move || {
+        let started_at = Instant::now();
+        let mut sequence = 0u64;
+        loop {
+            let grabbed_at = Instant::now();
+            let (spec, flip) = match capture.capture() {
+                Ok(captured) => captured,
+                Err(e) => {
+                    eprintln!("Screencopy error: {}", e);
+                    return;
+                }
+            };
+            let Some(format) = video_format(spec.format) else {
+                eprintln!("Screencopy error: unsupported shm format {:?}", spec.format);
+                return;
+            };
+
+            // Rows may be padded past width * 4, so copy them by stride
+            let step = thinning_step(spec.width, spec.height, &settings);
+            let data = copy_thinned(
+                capture.buffer.as_ref().unwrap().bytes(),
+                spec.width,
+                spec.height,
+                spec.stride as usize,
+                step,
+                flip,
+            );
+            let (width, height) = (spec.width.div_ceil(step), spec.height.div_ceil(step));
+
+            sequence += 1;
+            *stream_info.lock().unwrap() = Some(StreamInfo {
+                format: format_name(format),
+                width,
+                height,
+                framerate,
+                variable_framerate: false,
+                delivered_fps: sequence as f64 / started_at.elapsed().as_secs_f64(),
+                output: Some(capture.output_name.clone()),
+            });
+
+            let frame = FrameData {
+                data,
+                width,
+                height,
+                format,
+                timestamp: grabbed_at,
+                sequence,
+            };
+
+            // Monitoring was stopped
+            if frame_sender.send(Some(Arc::new(frame))).is_err() {
+                return;
+            }
+
+            thread::sleep(interval.saturating_sub(grabbed_at.elapsed()));
+        }
+    });
+
+    Ok(frame_receiver)
+}
+
+impl FrameSource for ScreencopySource {
+    fn name(&self) -> &'static str {
+        "wlr-screencopy"
+    }
+
+    fn start<'a>(&'a self, settings: &'a CaptureSettings) -> StartFuture<'a> {
+        *self.stream_info.lock().unwrap() = None;
+        let started = start_output_capture(settings, self.stream_info.clone());
+        Box::pin(async move { started })
+    }
+
+    fn stop(&self) {
+        *self.stream_info.lock().unwrap() = None;
+    }
+
+    fn metadata(&self) -> Option<StreamInfo> {
+        self.stream_info.lock().unwrap().clone()
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_registry::WlRegistry,
+        _: wl_registry::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, ()> for State {
+    fn event(
+        state: &mut Self,
+        output: &wl_output::WlOutput,
+        event: wl_output::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_output::Event::Name { name } = event {
+            state.output_names.push((output.clone(), name));
+        }
+    }
+}
+
+impl Dispatch<WlrFrame, ()> for State {
+    fn event(state: &mut Self, _: &WlrFrame, event: zwlr_screencopy_frame_v1::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+        match event {
+            zwlr_screencopy_frame_v1::Event::Buffer { format: WEnum::Value(format), width, height, stride } => {
+                state.offered = Some(BufferSpec { format, width, height, stride });
+            },
+            zwlr_screencopy_frame_v1::Event::Flags { flags: WEnum::Value(flags) } => {
+                state.y_invert = flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert);
+            },
+            zwlr_screencopy_frame_v1::Event::BufferDone => state.buffer_done = true,
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => state.outcome = Outcome::Ready,
+            zwlr_screencopy_frame_v1::Event::Failed => state.outcome = Outcome::Failed,
+            _ => {},
+        }
+    }
+}
+
+impl Dispatch<ExtSession, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ExtSession,
+        event: ext_image_copy_capture_session_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            // A new round of constraints follows whenever the output changes
+            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
+                state.session_size = Some((width, height));
+                state.session_formats.clear();
+            },
+            ext_image_copy_capture_session_v1::Event::ShmFormat { format: WEnum::Value(format) } => {
+                state.session_formats.push(format);
+            },
+            ext_image_copy_capture_session_v1::Event::Done => state.session_done = true,
+            ext_image_copy_capture_session_v1::Event::Stopped => {
+                state.session_stopped = true;
+                state.outcome = Outcome::Failed;
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Dispatch<ExtFrame, ()> for State {
+    fn event(state: &mut Self, _: &ExtFrame, event: ext_image_copy_capture_frame_v1::Event, _: &(), _: &Connection, _: &QueueHandle<Self>) {
+        match event {
+            ext_image_copy_capture_frame_v1::Event::Ready => state.outcome = Outcome::Ready,
+            ext_image_copy_capture_frame_v1::Event::Failed { .. } => state.outcome = Outcome::Failed,
+            _ => {},
+        }
+    }
+}
+
+delegate_noop!(State: ignore wl_shm::WlShm);
+delegate_noop!(State: wl_shm_pool::WlShmPool);
+delegate_noop!(State: ignore wl_buffer::WlBuffer);
+delegate_noop!(State: WlrManager);
+delegate_noop!(State: ExtCopyManager);
+delegate_noop!(State: ExtOutputSourceManager);
+delegate_noop!(State: ext_image_capture_source_v1::ExtImageCaptureSourceV1);
@@ -0,0 +1,278 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::path::PathBuf;
+
+use crate::clock::unix_millis;
+use crate::exposure::{DayExposure, ExposureTracker};
+use crate::preferences::solve;
+use crate::storage::{load_json, save_json};
+
+// Below this many nights with exposure data there is nothing worth fitting
+const MIN_NIGHTS: usize = 6;
+const MAX_FOLDS: usize = 5;
+const RIDGE_LAMBDA: f64 = 0.1;
+const FEATURE_COUNT: usize = 4;
+// Late evening is 22:00 until the day ends at 04:00
+const LATE_HOURS: [usize; 6] = [22, 23, 0, 1, 2, 3];
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SleepLog {
+    // The exposure day the night belongs to (see exposure.rs)
+    pub date: String,
+    pub latency_minutes: f64,
+    pub logged_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SleepCoefficients {
+    pub intercept: f64,
+    pub evening_dose: f64,
+    pub late_dose: f64,
+    pub evening_screen_hours: f64,
+}
+
+// Out-of-fold errors, as described in docs/metrics.md
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Evaluation {
+    pub folds: usize,
+    pub mae: f64,
+    pub rmse: f64,
+    // None when every logged latency is the same
+    pub r_squared: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SleepInsights {
+    pub logged_nights: usize,
+    pub usable_nights: usize,
+    pub min_nights: usize,
+    pub coefficients: Option<SleepCoefficients>,
+    pub evaluation: Option<Evaluation>,
+    // Predicted latency after the exposure so far today
+    pub tonight_minutes: Option<f64>,
+}
+
+// Sleep latency as reported by the user, one entry per night
+pub struct SleepJournal {
+    logs: Vec<SleepLog>,
+    storage_path: PathBuf,
+}
+
+impl SleepJournal {
+    pub fn load(storage_path: PathBuf) -> Self {
+        let logs = match load_json::<Vec<SleepLog>>(&storage_path) {
+            Ok(logs) => logs.unwrap_or_default(),
+            Err(e) => {
+                eprintln!("Ignoring unreadable sleep journal {}: {}", storage_path.display(), e);
+                Vec::new()
+            }
+        };
+
+        Self { logs, storage_path }
+    }
+
+    pub fn logs(&self) -> &[SleepLog] {
+        &self.logs
+    }
+
+    // Logging a night again replaces the earlier entry
+    pub fn log(&mut self, date: String, latency_minutes: f64) -> SleepLog {
+        let entry = SleepLog { date, latency_minutes, logged_at: unix_millis() };
+        self.logs.retain(|log| log.date != entry.date);
+        self.logs.push(entry.clone());
+        self.logs.sort_by(|a, b| a.date.cmp(&b.date));
+
+        if let Err(e) = save_json(&self.storage_path, &self.logs) {
+            eprintln!("Failed to save sleep journal: {}", e);
+        }
+        entry
+    }
+}
+
+// Doses are in minutes at full blue intensity; hours keep the features on
+// comparable scales for the ridge penalty
+fn features(day: &DayExposure) -> [f64; FEATURE_COUNT] {
+    let late_dose: f64 = LATE_HOURS.iter().map(|&hour| day.hours[hour].dose).sum();
+    [1.0, day.evening.dose / 60.0, late_dose / 60.0, day.evening.screen_minutes / 60.0]
+}
+
+// Ridge regression through the normal equations; the intercept isn't penalised
+fn fit(samples: &[([f64; FEATURE_COUNT], f64)]) -> Option<[f64; FEATURE_COUNT]> {
+    let mut xtx = [[0.0; FEATURE_COUNT]; FEATURE_COUNT];
+    let mut xty = [0.0; FEATURE_COUNT];
+    for (x, y) in samples {
+        for i in 0..FEATURE_COUNT {
+            xty[i] += x[i] * y;
+            for j in 0..FEATURE_COUNT {
+                xtx[i][j] += x[i] * x[j];
+            }
+        }
+    }
+    for (i, row) in xtx.iter_mut().enumerate().skip(1) {
+        row[i] += RIDGE_LAMBDA;
+    }
+
+    solve(xtx, xty)
+}
+
+fn predict(weights: &[f64; FEATURE_COUNT], x: &[f64; FEATURE_COUNT]) -> f64 {
+    weights.iter().zip(x).map(|(w, x)| w * x).sum::<f64>().max(0.0)
+}
+
+// k-fold cross-validation with nights dealt round-robin into the folds, so
+// every fold spans the whole logging period
+fn cross_validate(samples: &[([f64; FEATURE_COUNT], f64)]) -> Option<Evaluation> {
+    let folds = samples.len().min(MAX_FOLDS);
+    let mut errors = Vec::with_capacity(samples.len());
+
+    for fold in 0..folds {
+        let training: Vec<_> = samples.iter().enumerate().filter(|(i, _)| i % folds != fold).map(|(_, s)| *s).collect();
+        let weights = fit(&training)?;
+        for (x, y) in samples.iter().skip(fold).step_by(folds) {
+            errors.push(predict(&weights, x) - y);
+        }
+    }
+
+    let n = errors.len() as f64;
+    let mean = samples.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let total: f64 = samples.iter().map(|(_, y)| (y - mean).powi(2)).sum();
+    let residual: f64 = errors.iter().map(|e| e * e).sum();
+
+    Some(Evaluation {
+        folds,
+        mae: errors.iter().map(|e| e.abs()).sum::<f64>() / n,
+        rmse: (residual / n).sqrt(),
+        r_squared: (total > 0.0).then(|| 1.0 - residual / total),
+    })
+}
+
+// Fits sleep latency against the evening's exposure. Nights logged while
+// Lumina wasn't running have no exposure data and are left out.
+pub fn insights(logs: &[SleepLog], exposure: &ExposureTracker, today: &str) -> SleepInsights {
+    let samples: Vec<([f64; FEATURE_COUNT], f64)> = logs
+        .iter()
+        .filter_map(|log| Some((features(exposure.day(&log.date)?), log.latency_minutes)))
+        .collect();
+
+    let mut insights = SleepInsights {
+        logged_nights: logs.len(),
+        usable_nights: samples.len(),
+        min_nights: MIN_NIGHTS,
+        coefficients: None,
+        evaluation: None,
+        tonight_minutes: None,
+    };
+    if samples.len() < MIN_NIGHTS {
+        return insights;
+    }
+
+    let Some(weights) = fit(&samples) else {
+        return insights;
+    };
+    insights.coefficients = Some(SleepCoefficients {
+        intercept: weights[0],
+        evening_dose: weights[1],
+        late_dose: weights[2],
+        evening_screen_hours: weights[3],
+    });
+    insights.evaluation = cross_validate(&samples);
+    insights.tonight_minutes = exposure.day(today).map(|day| predict(&weights, &features(day)));
+    insights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use crate::exposure::Exposure;
+
+    // Latency rises 12 minutes per hour-equivalent of evening dose and 25 per
+    // hour-equivalent after 22:00, on top of a 10-minute baseline
+    fn planted_latency(x: &[f64; FEATURE_COUNT]) -> f64 {
+        10.0 + 12.0 * x[1] + 25.0 * x[2]
+    }
+
+    // Deterministic noise in [0, 1), so no two features move together
+    fn noise(index: usize, stream: u64) -> f64 {
+        let mut state = (index as u64 + 1).wrapping_mul(6364136223846793005).wrapping_add(stream * 1442695040888963407);
+        state ^= state >> 33;
+        state = state.wrapping_mul(0xff51afd7ed558ccd);
+        state ^= state >> 33;
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn night(index: usize) -> DayExposure {
+        let evening_dose = 30.0 + 200.0 * noise(index, 1);
+        let late_dose = 90.0 * noise(index, 2);
+        let screen_minutes = 60.0 + 180.0 * noise(index, 3);
+
+        let mut hours = vec![Exposure::default(); 24];
+        hours[23] = Exposure { screen_minutes: 60.0, dose: late_dose };
+        DayExposure {
+            date: format!("2026-01-{:02}", index + 1),
+            total: Exposure { screen_minutes: screen_minutes + 300.0, dose: evening_dose + 100.0 },
+            evening: Exposure { screen_minutes, dose: evening_dose },
+            hours,
+            applications: BTreeMap::new(),
+        }
+    }
+
+    fn journal(name: &str, nights: usize) -> (Vec<SleepLog>, ExposureTracker) {
+        let path = crate::storage::test_dir(name).join("exposure.json");
+        let days: Vec<DayExposure> = (0..nights).map(night).collect();
+        save_json(&path, &days).unwrap();
+
+        let logs = days
+            .iter()
+            .map(|day| SleepLog { date: day.date.clone(), latency_minutes: planted_latency(&features(day)), logged_at: 0 })
+            .collect();
+        (logs, ExposureTracker::load(path))
+    }
+
+    #[test]
+    fn cross_validation_recovers_a_planted_relation() {
+        let samples: Vec<_> = (0..20)
+            .map(|index| {
+                let x = features(&night(index));
+                (x, planted_latency(&x))
+            })
+            .collect();
+
+        let evaluation = cross_validate(&samples).unwrap();
+        assert_eq!(evaluation.folds, MAX_FOLDS);
+        assert!(evaluation.r_squared.unwrap() > 0.99, "{:?}", evaluation);
+        assert!(evaluation.mae < 1.0, "{:?}", evaluation);
+    }
+
+    #[test]
+    fn insights_fit_the_planted_coefficients() {
+        let (logs, exposure) = journal("sleep-planted", 20);
+        let insights = insights(&logs, &exposure, "2026-01-03");
+
+        assert_eq!(insights.usable_nights, 20);
+        let coefficients = insights.coefficients.unwrap();
+        assert!((coefficients.intercept - 10.0).abs() < 1.0, "{:?}", coefficients);
+        assert!((coefficients.evening_dose - 12.0).abs() < 1.0, "{:?}", coefficients);
+        assert!((coefficients.late_dose - 25.0).abs() < 1.0, "{:?}", coefficients);
+        assert!(coefficients.evening_screen_hours.abs() < 1.0, "{:?}", coefficients);
+        assert!(insights.evaluation.unwrap().r_squared.unwrap() > 0.99);
+
+        let tonight = planted_latency(&features(&night(2)));
+        assert!((insights.tonight_minutes.unwrap() - tonight).abs() < 1.0);
+    }
+
+    #[test]
+    fn too_few_nights_give_no_model() {
+        let (mut logs, exposure) = journal("sleep-few", MIN_NIGHTS - 1);
+        // A night logged without exposure data doesn't count towards the minimum
+        logs.push(SleepLog { date: "2025-12-31".to_string(), latency_minutes: 20.0, logged_at: 0 });
+
+        let insights = insights(&logs, &exposure, "2026-01-01");
+        assert_eq!(insights.logged_nights, MIN_NIGHTS);
+        assert_eq!(insights.usable_nights, MIN_NIGHTS - 1);
+        assert!(insights.coefficients.is_none());
+        assert!(insights.evaluation.is_none());
+        assert!(insights.tonight_minutes.is_none());
+    }
+}
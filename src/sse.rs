@@ -0,0 +1,92 @@
+use actix_web::http::header;
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpRequest, HttpResponse};
+use futures_util::stream;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::events::{Event, StampedEvent};
+use crate::{status_snapshot, AppState};
+
+// Comment lines keep idle connections open through proxies and let us
+// notice clients that went away
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+// How long browsers wait before reconnecting
+const RETRY_MS: u64 = 3000;
+
+// GET /events: the same events as /ws, as Server-Sent Events. A client that
+// reconnects with Last-Event-ID gets what it missed from the recent-events
+// buffer; a new client, or one that missed more than the buffer holds,
+// starts from a status snapshot instead.
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    summary = "Live events (Server-Sent Events)",
+    params(
+        ("Last-Event-ID" = Option<u64>, Header, description = "Resume after this event id"),
+        ("ticket" = Option<String>, Query, description = "Short-lived ticket from POST /auth/ticket, for clients that can't send a bearer token"),
+    ),
+    responses(
+        (status = 200, description = "An event stream; each data line is one event", body = StampedEvent, content_type = "text/event-stream"),
+    )
+)]
+pub async fn event_stream(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    let mut pending = VecDeque::from([Bytes::from(format!("retry: {}\n\n", RETRY_MS))]);
+    let (missed, events) = match last_event_id {
+        Some(id) => data.events.subscribe_since(id),
+        None => (None, data.events.subscribe()),
+    };
+    match missed {
+        Some(missed) => {
+            pending.extend(missed.iter().map(|stamped| encode(Some(stamped.id), stamped.event.name(), stamped)));
+        },
+        None => pending.push_back(snapshot(&data)),
+    }
+
+    let body = stream::unfold((pending, events, data), |(mut pending, mut events, data)| async move {
+        if let Some(chunk) = pending.pop_front() {
+            return Some((Ok::<_, actix_web::Error>(chunk), (pending, events, data)));
+        }
+
+        let chunk = tokio::select! {
+            event = events.recv() => match event {
+                Ok(stamped) => encode(Some(stamped.id), stamped.event.name(), &stamped),
+                // Too slow to keep up; resynchronise from a fresh snapshot
+                Err(RecvError::Lagged(_)) => snapshot(&data),
+                Err(RecvError::Closed) => return None,
+            },
+            _ = tokio::time::sleep(KEEPALIVE_INTERVAL) => Bytes::from_static(b": keepalive\n\n"),
+        };
+        Some((Ok(chunk), (pending, events, data)))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(body)
+}
+
+// Snapshots carry no id, so a reconnect resumes from the last real event
+fn snapshot(data: &AppState) -> Bytes {
+    let event = Event::Status { status: Box::new(status_snapshot(data)) };
+    encode(None, event.name(), &event)
+}
+
+// The data line is the same JSON the WebSocket sends
+fn encode(id: Option<u64>, name: &str, value: &impl Serialize) -> Bytes {
+    let json = serde_json::to_string(value).expect("events serialize to JSON");
+    let message = match id {
+        Some(id) => format!("id: {}\nevent: {}\ndata: {}\n\n", id, name, json),
+        None => format!("event: {}\ndata: {}\n\n", name, json),
+    };
+    Bytes::from(message)
+}
@@ -0,0 +1,305 @@
+use pipewire::spa::param::video::VideoFormat;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::capture::{
+    format_name, parse_format, CaptureSettings, FrameData, FrameSource, SharedStreamInfo, StartFuture, StreamInfo,
+};
+
+const DEFAULT_SWITCH_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    Solid([u8; 3]),
+    // Red rises left to right, blue top to bottom, green stays at half
+    Gradient,
+    // Alternates a dark and a light screen every period
+    Switch(Duration),
+    Noise,
+}
+
+impl Pattern {
+    // "solid", "solid:#1e90ff", "gradient", "switch", "switch:5" or "noise"
+    fn parse(spec: &str) -> Result<Self, String> {
+        let (name, argument) = match spec.split_once(':') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (spec, None),
+        };
+
+        match (name, argument) {
+            ("solid", None) => Ok(Pattern::Solid([128, 128, 128])),
+            ("solid", Some(color)) => parse_color(color).map(Pattern::Solid),
+            ("gradient", None) => Ok(Pattern::Gradient),
+            ("switch", None) => Ok(Pattern::Switch(Duration::from_secs(DEFAULT_SWITCH_SECS))),
+            ("switch", Some(secs)) => match secs.parse::<u64>() {
+                Ok(secs) if secs > 0 => Ok(Pattern::Switch(Duration::from_secs(secs))),
+                _ => Err(format!("invalid switch period '{}'", secs)),
+            },
+            ("noise", None) => Ok(Pattern::Noise),
+            _ => Err(format!("unknown synthetic pattern '{}'", spec)),
+        }
+    }
+}
+
+fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let hex = color.trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or_else(|| format!("invalid color '{}', expected #rrggbb", color))?;
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+// Generates frames instead of capturing them, so the server, analysis and
+// controller run end to end without PipeWire or a portal. Chosen with
+// `--synthetic <pattern>` and optionally `--synthetic-format <format>`.
+pub struct SyntheticSource {
+    pattern: Pattern,
+    format: VideoFormat,
+    stream_info: SharedStreamInfo,
+}
+
+impl SyntheticSource {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut pattern = None;
+        let mut format = VideoFormat::BGRx;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--synthetic" => {
+                    let spec = args.next().ok_or("--synthetic needs a pattern")?;
+                    pattern = Some(Pattern::parse(&spec)?);
+                },
+                "--synthetic-format" => {
+                    let name = args.next().ok_or("--synthetic-format needs a format")?;
+                    format = parse_format(&name).ok_or_else(|| format!("unsupported format '{}'", name))?;
+                },
+                _ => return Err(format!("unknown argument '{}'", arg)),
+            }
+        }
+
+        Ok(pattern.map(|pattern| Self { pattern, format, stream_info: SharedStreamInfo::default() }))
+    }
+}
+
+impl FrameSource for SyntheticSource {
+    fn name(&self) -> &'static str {
+        "synthetic"
+    }
+
+    // Frames come at the configured interval and preferred size
+    fn start<'a>(&'a self, settings: &'a CaptureSettings) -> StartFuture<'a> {
+        // The YUV formats share chroma between pixel pairs
+        let width = (settings.preferred_width & !1).max(2);
+        let height = (settings.preferred_height & !1).max(2);
+        let interval = settings.poll_interval();
+        let fps = 1.0 / interval.as_secs_f64();
+        *self.stream_info.lock().unwrap() = Some(StreamInfo {
+            format: format_name(self.format),
+            width,
+            height,
+            framerate: fps,
+            variable_framerate: false,
+            delivered_fps: fps,
+            output: None,
+        });
+
+        let (frame_sender, frame_receiver) = watch::channel(None);
+        let pattern = self.pattern;
+        let format = self.format;
+
+        thread::spawn(move || {
+            let started_at = Instant::now();
+            let mut rng = Xorshift::seeded();
+            let mut sequence = 0u64;
+            loop {
+                let elapsed = started_at.elapsed();
+                let data = encode(format, width, height, |x, y| match pattern {
+                    Pattern::Solid(color) => color,
+                    Pattern::Gradient => [(x * 255 / (width - 1)) as u8, 128, (y * 255 / (height - 1)) as u8],
+                    Pattern::Switch(period) => {
+                        let light = (elapsed.as_millis() / period.as_millis()) % 2 == 1;
+                        if light { [235, 235, 235] } else { [20, 20, 24] }
+                    },
+                    Pattern::Noise => {
+                        let value = rng.next();
+                        [value as u8, (value >> 8) as u8, (value >> 16) as u8]
+                    },
+                });
+
+                sequence += 1;
+                let frame = FrameData {
+                    data,
+                    width,
+                    height,
+                    format,
+                    timestamp: Instant::now(),
+                    sequence,
+                };
+
+                // Monitoring was stopped
+                if frame_sender.send(Some(Arc::new(frame))).is_err() {
+                    return;
+                }
+
+                thread::sleep(interval);
+            }
+        });
+
+        Box::pin(async move { Ok(frame_receiver) })
+    }
+
+    fn stop(&self) {
+        *self.stream_info.lock().unwrap() = None;
+    }
+
+    fn metadata(&self) -> Option<StreamInfo> {
+        self.stream_info.lock().unwrap().clone()
+    }
+}
+
+// Lays out an RGB picture the way each negotiated format stores it
+fn encode(format: VideoFormat, width: u32, height: u32, mut pixel: impl FnMut(u32, u32) -> [u8; 3]) -> Vec<u8> {
+    let (w, h) = (width as usize, height as usize);
+    let mut rgb = Vec::with_capacity(w * h);
+    for y in 0..height {
+        for x in 0..width {
+            rgb.push(pixel(x, y));
+        }
+    }
+
+    match format {
+        VideoFormat::RGB => rgb.iter().flatten().copied().collect(),
+        VideoFormat::RGBA | VideoFormat::RGBx => rgb.iter().flat_map(|&[r, g, b]| [r, g, b, 255]).collect(),
+        VideoFormat::BGRx => rgb.iter().flat_map(|&[r, g, b]| [b, g, r, 255]).collect(),
+        VideoFormat::YUY2 => rgb
+            .chunks_exact(2)
+            .flat_map(|pair| {
+                let (y0, u0, v0) = to_yuv(pair[0]);
+                let (y1, u1, v1) = to_yuv(pair[1]);
+                [y0, ((u0 as u16 + u1 as u16) / 2) as u8, y1, ((v0 as u16 + v1 as u16) / 2) as u8]
+            })
+            .collect(),
+        VideoFormat::I420 => {
+            let mut data: Vec<u8> = rgb.iter().map(|&color| to_yuv(color).0).collect();
+            // Chroma planes are subsampled 2x2; the top-left pixel stands in
+            let (mut u_plane, mut v_plane) = (Vec::new(), Vec::new());
+            for y in (0..h).step_by(2) {
+                for x in (0..w).step_by(2) {
+                    let (_, u, v) = to_yuv(rgb[y * w + x]);
+                    u_plane.push(u);
+                    v_plane.push(v);
+                }
+            }
+            data.extend(u_plane);
+            data.extend(v_plane);
+            data
+        },
+        _ => unreachable!("parse_format only yields negotiated formats"),
+    }
+}
+
+// Full-range BT.601
+fn to_yuv([r, g, b]: [u8; 3]) -> (u8, u8, u8) {
+    let (r, g, b) = (r as f64, g as f64, b as f64);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let u = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let v = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    (y.round() as u8, u.round().clamp(0.0, 255.0) as u8, v.round().clamp(0.0, 255.0) as u8)
+}
+
+struct Xorshift(u64);
+
+impl Xorshift {
+    fn seeded() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Self(nanos | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Black, white, red and blue, left to right then top to bottom
+    const PIXELS: [[u8; 3]; 4] = [[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 0, 255]];
+
+    fn picture(format: VideoFormat) -> Vec<u8> {
+        encode(format, 2, 2, |x, y| PIXELS[(y * 2 + x) as usize])
+    }
+
+    #[test]
+    fn packed_rgb_layouts() {
+        assert_eq!(picture(VideoFormat::RGB), [0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 255]);
+        assert_eq!(
+            picture(VideoFormat::RGBx),
+            [0, 0, 0, 255, 255, 255, 255, 255, 255, 0, 0, 255, 0, 0, 255, 255]
+        );
+        assert_eq!(picture(VideoFormat::RGBA), picture(VideoFormat::RGBx));
+        assert_eq!(
+            picture(VideoFormat::BGRx),
+            [0, 0, 0, 255, 255, 255, 255, 255, 0, 0, 255, 255, 255, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn yuv_layouts() {
+        assert_eq!(to_yuv([0, 0, 0]), (0, 128, 128));
+        assert_eq!(to_yuv([255, 255, 255]), (255, 128, 128));
+        let (red_y, red_u, red_v) = to_yuv([255, 0, 0]);
+        let (blue_y, blue_u, blue_v) = to_yuv([0, 0, 255]);
+        assert_eq!((red_y, blue_y), (76, 29));
+
+        // Y0 U Y1 V per pixel pair, chroma averaged over the pair
+        let yuy2 = picture(VideoFormat::YUY2);
+        assert_eq!(yuy2.len(), 8);
+        assert_eq!(yuy2[..4], [0, 128, 255, 128]);
+        let average = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+        assert_eq!(yuy2[4..], [red_y, average(red_u, blue_u), blue_y, average(red_v, blue_v)]);
+
+        // Full Y plane, then one U and one V sample from the top-left pixel
+        assert_eq!(picture(VideoFormat::I420), [0, 255, red_y, blue_y, 128, 128]);
+        let i420 = encode(VideoFormat::I420, 4, 2, |x, _| if x < 2 { [0, 0, 0] } else { [255, 0, 0] });
+        assert_eq!(i420.len(), 4 * 2 + 2 + 2);
+        assert_eq!(i420[8..], [128, red_u, 128, red_v]);
+    }
+
+    #[test]
+    fn parses_patterns() {
+        assert_eq!(Pattern::parse("solid"), Ok(Pattern::Solid([128, 128, 128])));
+        assert_eq!(Pattern::parse("solid:#1e90ff"), Ok(Pattern::Solid([0x1e, 0x90, 0xff])));
+        assert_eq!(Pattern::parse("solid:1E90FF"), Ok(Pattern::Solid([0x1e, 0x90, 0xff])));
+        assert_eq!(Pattern::parse("gradient"), Ok(Pattern::Gradient));
+        assert_eq!(Pattern::parse("switch"), Ok(Pattern::Switch(Duration::from_secs(DEFAULT_SWITCH_SECS))));
+        assert_eq!(Pattern::parse("switch:5"), Ok(Pattern::Switch(Duration::from_secs(5))));
+        assert_eq!(Pattern::parse("noise"), Ok(Pattern::Noise));
+
+        for spec in ["", "solid:#fff", "solid:#12345g", "solid:", "switch:0", "switch:x", "gradient:1", "plaid"] {
+            assert!(Pattern::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let args = |args: &[&str]| SyntheticSource::from_args(args.iter().map(|arg| arg.to_string()));
+        assert!(args(&[]).unwrap().is_none());
+
+        let source = args(&["--synthetic", "noise", "--synthetic-format", "I420"]).unwrap().unwrap();
+        assert_eq!((source.pattern, source.format), (Pattern::Noise, VideoFormat::I420));
+        assert!(args(&["--synthetic"]).is_err());
+        assert!(args(&["--synthetic", "noise", "--synthetic-format", "NV12"]).is_err());
+        assert!(args(&["--verbose"]).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FocusedWindow {
+    pub app_id: String,
+    pub title: String,
+    pub fullscreen: bool,
+}
+
+pub trait WindowTracker: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn focused_window(&self) -> Option<FocusedWindow>;
+}
+
+// Picks a tracker for the running session. LUMINA_MOCK_FOCUSED_APP pins the
+// focused application (and LUMINA_MOCK_FULLSCREEN=1 marks it fullscreen),
+// which is handy without a compositor at hand.
+pub fn detect_window_tracker() -> Box<dyn WindowTracker> {
+    if let Ok(app_id) = std::env::var("LUMINA_MOCK_FOCUSED_APP") {
+        let fullscreen = std::env::var("LUMINA_MOCK_FULLSCREEN").is_ok_and(|value| value == "1");
+        let tracker = MockWindowTracker::default();
+        tracker.set_focused(Some(FocusedWindow { app_id, title: String::new(), fullscreen }));
+        return Box::new(tracker);
+    }
+
+    if std::env::var_os("SWAYSOCK").is_some() {
+        return Box::new(SwayTracker);
+    }
+
+    // Under an X session xprop sees every window, GNOME/Xorg included
+    let x11_session = std::env::var("XDG_SESSION_TYPE").is_ok_and(|session| session == "x11");
+    let display = std::env::var_os("DISPLAY").is_some();
+    if x11_session && display {
+        return Box::new(X11Tracker);
+    }
+
+    let desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+    if desktop.split(':').any(|name| name.eq_ignore_ascii_case("GNOME")) {
+        if GnomeShellTracker::available() {
+            return Box::new(GnomeShellTracker);
+        }
+        eprintln!("GNOME focus tracking needs the lumina-focus@lumina Shell extension (see gnome-extension/)");
+    }
+
+    if display {
+        if !x11_session {
+            eprintln!("Falling back to xprop for focus tracking; only XWayland windows will be seen");
+        }
+        return Box::new(X11Tracker);
+    }
+
+    eprintln!("Focused window tracking is unavailable; per-application rules and fullscreen freezing won't apply");
+    Box::new(NoWindowTracker)
+}
+
+pub struct NoWindowTracker;
+
+impl WindowTracker for NoWindowTracker {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    fn focused_window(&self) -> Option<FocusedWindow> {
+        None
+    }
+}
+
+#[derive(Default)]
+pub struct MockWindowTracker {
+    focused: Mutex<Option<FocusedWindow>>,
+}
+
+impl MockWindowTracker {
+    pub fn set_focused(&self, window: Option<FocusedWindow>) {
+        *self.focused.lock().unwrap() = window;
+    }
+}
+
+impl WindowTracker for MockWindowTracker {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn focused_window(&self) -> Option<FocusedWindow> {
+        self.focused.lock().unwrap().clone()
+    }
+}
+
+// Walks the sway IPC tree (via swaymsg) for the focused container
+pub struct SwayTracker;
+
+impl WindowTracker for SwayTracker {
+    fn name(&self) -> &'static str {
+        "sway"
+    }
+
+    fn focused_window(&self) -> Option<FocusedWindow> {
+        let output = Command::new("swaymsg")
+            .args(["-t", "get_tree", "-r"])
+            .output()
+            .ok()?;
+        let tree: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+        let node = find_focused_sway_node(&tree)?;
+
+        // Native Wayland clients report app_id, XWayland ones only a class
+        let app_id = node["app_id"]
+            .as_str()
+            .or_else(|| node["window_properties"]["class"].as_str())?
+            .to_string();
+        let title = node["name"].as_str().unwrap_or_default().to_string();
+        let fullscreen = node["fullscreen_mode"].as_u64().is_some_and(|mode| mode != 0);
+
+        Some(FocusedWindow { app_id, title, fullscreen })
+    }
+}
+
+fn find_focused_sway_node(node: &serde_json::Value) -> Option<&serde_json::Value> {
+    if node["focused"].as_bool() == Some(true) {
+        return Some(node);
+    }
+
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[key].as_array())
+        .flatten()
+        .find_map(find_focused_sway_node)
+}
+
+// Reads _NET_ACTIVE_WINDOW from the root window, then its class and state
+pub struct X11Tracker;
+
+impl WindowTracker for X11Tracker {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn focused_window(&self) -> Option<FocusedWindow> {
+        let root = Command::new("xprop")
+            .args(["-root", "_NET_ACTIVE_WINDOW"])
+            .output()
+            .ok()?;
+        let root = String::from_utf8_lossy(&root.stdout);
+        let window_id = root.split_whitespace().last()?.trim_end_matches(',');
+        if window_id == "0x0" {
+            return None;
+        }
+
+        let props = Command::new("xprop")
+            .args(["-id", window_id, "WM_CLASS", "_NET_WM_NAME", "_NET_WM_STATE"])
+            .output()
+            .ok()?;
+        let props = String::from_utf8_lossy(&props.stdout);
+
+        let mut app_id = None;
+        let mut title = String::new();
+        let mut fullscreen = false;
+        for line in props.lines() {
+            let Some((key, value)) = line.split_once(" = ") else {
+                continue;
+            };
+            let values: Vec<&str> = value.split(", ").map(|v| v.trim_matches('"')).collect();
+            if key.starts_with("WM_CLASS") {
+                // WM_CLASS is "instance", "Class"; the class is the stable identifier
+                app_id = values.last().map(|class| class.to_string());
+            } else if key.starts_with("_NET_WM_NAME") {
+                title = values.join(", ");
+            } else if key.starts_with("_NET_WM_STATE") {
+                fullscreen = values.contains(&"_NET_WM_STATE_FULLSCREEN");
+            }
+        }
+
+        Some(FocusedWindow { app_id: app_id?, title, fullscreen })
+    }
+}
+
+// Asks the lumina-focus GNOME Shell extension (gnome-extension/ in this
+// repository) over D-Bus. Stock GNOME refuses org.gnome.Shell.Eval since 41
+// and Wayland offers no other way to learn the focused window.
+pub struct GnomeShellTracker;
+
+impl GnomeShellTracker {
+    // Whether the extension is installed and enabled in this session
+    pub fn available() -> bool {
+        Self::call().is_some()
+    }
+
+    // The extension's JSON reply, or None if it isn't running
+    fn call() -> Option<String> {
+        let output = Command::new("gdbus")
+            .args([
+                "call",
+                "--session",
+                "--dest", "io.github.lumina.FocusedWindow",
+                "--object-path", "/io/github/lumina/FocusedWindow",
+                "--method", "io.github.lumina.FocusedWindow.Get",
+            ])
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        parse_gvariant_string(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+impl WindowTracker for GnomeShellTracker {
+    fn name(&self) -> &'static str {
+        "gnome-shell"
+    }
+
+    fn focused_window(&self) -> Option<FocusedWindow> {
+        let reply = Self::call()?;
+        let window: FocusedWindow = serde_json::from_str(&reply).ok()?;
+        (!window.app_id.is_empty()).then_some(window)
+    }
+}
+
+// gdbus prints a one-string tuple as ('...',), backslash-escaping quotes and
+// backslashes inside the string
+fn parse_gvariant_string(reply: &str) -> Option<String> {
+    let quoted = reply.trim().strip_prefix('(')?.strip_suffix(",)")?;
+    let quote = quoted.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let inner = quoted.strip_prefix(quote)?.strip_suffix(quote)?;
+
+    let mut value = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        value.push(if c == '\\' { chars.next()? } else { c });
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_extension_reply() {
+        let reply = r#"('{"app_id":"org.gnome.Nautilus","title":"Bob\'s \\"files\\"","fullscreen":false}',)"#;
+        let json = parse_gvariant_string(reply).unwrap();
+        let window: FocusedWindow = serde_json::from_str(&json).unwrap();
+        assert_eq!(window.app_id, "org.gnome.Nautilus");
+        assert_eq!(window.title, "Bob's \"files\"");
+        assert!(!window.fullscreen);
+    }
+
+    #[test]
+    fn empty_reply_means_no_focus() {
+        assert_eq!(parse_gvariant_string("('',)\n").as_deref(), Some(""));
+        assert!(parse_gvariant_string("Error: GDBus.Error").is_none());
+    }
+
+    #[test]
+    fn mock_reports_what_it_was_given() {
+        let tracker = MockWindowTracker::default();
+        assert!(tracker.focused_window().is_none());
+        tracker.set_focused(Some(FocusedWindow { app_id: "mpv".into(), title: String::new(), fullscreen: true }));
+        assert_eq!(tracker.focused_window().unwrap().app_id, "mpv");
+    }
+}
@@ -0,0 +1,123 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use actix_ws::{Message, Session};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::error::{ApiError, ErrorBody};
+use crate::events::{Event, StampedEvent};
+use crate::{apply_config_update, status_snapshot, AppState, NightLightConfig, UpdateConfigRequest};
+
+// Messages a client may send. `id` is echoed back so replies can be matched
+// to the command that caused them.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    UpdateConfig { id: Option<u64>, changes: UpdateConfigRequest },
+}
+
+// Replies go only to the client that sent the command; the resulting
+// config_changed event still goes to everyone
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    CommandResult { id: Option<u64>, config: NightLightConfig },
+    // Same code, message and details as the HTTP error responses
+    CommandError {
+        id: Option<u64>,
+        #[serde(flatten)]
+        error: ErrorBody,
+    },
+}
+
+// GET /ws: a snapshot first, then every event as it happens
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "events",
+    summary = "Live events and config commands (WebSocket)",
+    description = "Sends every event as a JSON text message. Clients may send \
+        {\"type\": \"update_config\", \"id\": 1, \"changes\": {...}} with the same fields as PUT /config; \
+        the reply is a command_result with the new config or a command_error with an error body.",
+    params(
+        ("ticket" = Option<String>, Query, description = "Short-lived ticket from POST /auth/ticket, for clients that can't send a bearer token"),
+    ),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol; messages are events", body = StampedEvent),
+    )
+)]
+pub async fn events_socket(
+    req: HttpRequest,
+    body: web::Payload,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    let mut events = data.events.subscribe();
+
+    actix_web::rt::spawn(async move {
+        let snapshot = Event::Status { status: Box::new(status_snapshot(&data)) };
+        if send_json(&mut session, &snapshot).await.is_err() {
+            return;
+        }
+
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let sent = match event {
+                        Ok(stamped) => send_json(&mut session, &stamped).await,
+                        // Too slow to keep up; resynchronise from a fresh snapshot
+                        Err(RecvError::Lagged(_)) => {
+                            let snapshot = Event::Status { status: Box::new(status_snapshot(&data)) };
+                            send_json(&mut session, &snapshot).await
+                        },
+                        Err(RecvError::Closed) => break,
+                    };
+                    if sent.is_err() {
+                        return;
+                    }
+                }
+                message = messages.recv() => {
+                    let reply = match message {
+                        Some(Ok(Message::Text(text))) => handle_command(&data, &text),
+                        Some(Ok(Message::Ping(bytes))) => {
+                            if session.pong(&bytes).await.is_err() {
+                                return;
+                            }
+                            continue;
+                        },
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => continue,
+                    };
+                    if send_json(&mut session, &reply).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
+
+fn handle_command(data: &AppState, text: &str) -> Reply {
+    let command: Command = match serde_json::from_str(text) {
+        Ok(command) => command,
+        Err(e) => {
+            let error = ApiError::InvalidRequest { message: format!("Invalid command: {}", e) };
+            return Reply::CommandError { id: None, error: error.body() };
+        },
+    };
+
+    match command {
+        Command::UpdateConfig { id, changes } => match apply_config_update(data, &changes) {
+            Ok(config) => Reply::CommandResult { id, config },
+            Err(error) => Reply::CommandError { id, error: error.body() },
+        },
+    }
+}
+
+async fn send_json(session: &mut Session, value: &impl Serialize) -> Result<(), actix_ws::Closed> {
+    let text = serde_json::to_string(value).expect("events serialize to JSON");
+    session.text(text).await
+}
@@ -0,0 +1,277 @@
+use pipewire::spa::param::video::VideoFormat;
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tokio::sync::watch;
+use x11rb::connection::Connection;
+use x11rb::protocol::shm::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{ChangeWindowAttributesAux, ConnectionExt as _, EventMask, ImageFormat, Window};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+use crate::capture::{
+    copy_thinned, format_name, thinning_step, CaptureError, CaptureSettings, FrameData, FrameReceiver, FrameSource,
+    SharedStreamInfo, StartFuture, StreamInfo,
+};
+
+// Grabs the root window with MIT-SHM GetImage, for X11 sessions where no
+// ScreenCast portal is running. The server writes straight into a SysV
+// shared memory segment, so each grab costs a single round trip.
+#[derive(Default)]
+pub struct X11ShmSource {
+    stream_info: SharedStreamInfo,
+}
+
+// A SysV segment attached both here and in the X server
+struct SharedSegment {
+    addr: *mut u8,
+    len: usize,
+    seg: shm::Seg,
+}
+
+// The mapping is only touched from the capture thread
+unsafe impl Send for SharedSegment {}
+
+impl SharedSegment {
+    fn attach(conn: &RustConnection, len: usize) -> Result<Self, CaptureError> {
+        let seg = conn.generate_id()?;
+        let id = unsafe { libc::shmget(libc::IPC_PRIVATE, len, libc::IPC_CREAT | 0o600) };
+        if id < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let addr = unsafe { libc::shmat(id, ptr::null(), libc::SHM_RDONLY) };
+        if addr as isize == -1 {
+            let error = std::io::Error::last_os_error();
+            unsafe { libc::shmctl(id, libc::IPC_RMID, ptr::null_mut()) };
+            return Err(error.into());
+        }
+
+        let segment = Self { addr: addr as *mut u8, len, seg };
+        let attached = conn.shm_attach(seg, id as u32, false).map(|cookie| cookie.check());
+        // Marked for removal straight away: the kernel frees the segment once
+        // the last side detaches, even if we crash or the server never attached
+        unsafe { libc::shmctl(id, libc::IPC_RMID, ptr::null_mut()) };
+        attached??;
+
+        Ok(segment)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.addr, self.len) }
+    }
+}
+
+impl Drop for SharedSegment {
+    fn drop(&mut self) {
+        unsafe { libc::shmdt(self.addr as *const libc::c_void) };
+    }
+}
+
+struct RootGrabber {
+    conn: RustConnection,
+    root: Window,
+    width: u16,
+    height: u16,
+    segment: SharedSegment,
+}
+
+impl RootGrabber {
+    fn open() -> Result<Self, CaptureError> {
+        let (conn, screen_num) = x11rb::connect(None)?;
+        conn.shm_query_version()?.reply()?;
+
+        let screen = &conn.setup().roots[screen_num];
+        let (root, width, height, depth) =
+            (screen.root, screen.width_in_pixels, screen.height_in_pixels, screen.root_depth);
+
+        // ZPixmap data is BGRx on little-endian hosts only when the root
+        // depth is stored in 32 bits per pixel
+        let bits_per_pixel = conn
+            .setup()
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == depth)
+            .map(|format| format.bits_per_pixel);
+        if bits_per_pixel != Some(32) || cfg!(target_endian = "big") {
+            return Err(format!("unsupported root visual (depth {}, {:?} bits per pixel)", depth, bits_per_pixel).into());
+        }
+
+        // ConfigureNotify on the root tells us when the screen is resized
+        conn.change_window_attributes(root, &ChangeWindowAttributesAux::new().event_mask(EventMask::STRUCTURE_NOTIFY))?
+            .check()?;
+
+        let segment = SharedSegment::attach(&conn, width as usize * height as usize * 4)?;
+        Ok(Self { conn, root, width, height, segment })
+    }
+
+    // Follows the root window to a new size, e.g. after a RandR change.
+    // Returns true when the size changed.
+    fn follow_resize(&mut self) -> Result<bool, CaptureError> {
+        let mut size = None;
+        while let Some(event) = self.conn.poll_for_event()? {
+            if let Event::ConfigureNotify(event) = event
+                && event.window == self.root
+            {
+                size = Some((event.width, event.height));
+            }
+        }
+        let Some((width, height)) = size.filter(|&size| size != (self.width, self.height)) else {
+            return Ok(false);
+        };
+
+        let segment = SharedSegment::attach(&self.conn, width as usize * height as usize * 4)?;
+        let previous = std::mem::replace(&mut self.segment, segment);
+        self.conn.shm_detach(previous.seg)?.check()?;
+        self.width = width;
+        self.height = height;
+        Ok(true)
+    }
+
+    // Whether the root window no longer has the size being grabbed
+    fn size_changed(&self) -> bool {
+        self.conn
+            .get_geometry(self.root)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .is_some_and(|geometry| (geometry.width, geometry.height) != (self.width, self.height))
+    }
+
+    fn grab(&self) -> Result<&[u8], CaptureError> {
+        let reply = self
+            .conn
+            .shm_get_image(
+                self.root,
+                0,
+                0,
+                self.width,
+                self.height,
+                !0,
+                ImageFormat::Z_PIXMAP.into(),
+                self.segment.seg,
+                0,
+            )?
+            .reply()?;
+        let size = (reply.size as usize).min(self.segment.len);
+        Ok(&self.segment.bytes()[..size])
+    }
+}
+
+impl Drop for RootGrabber {
+    fn drop(&mut self) {
+        if let Ok(cookie) = self.conn.shm_detach(self.segment.seg) {
+            cookie.ignore_error();
+        }
+    }
+}
+
+// Output size and thinning step for a root window of the given size
+fn frame_geometry(width: u32, height: u32, settings: &CaptureSettings) -> (u32, u32, u32) {
+    // The server can't scale for us, so thin out rows and columns instead
+    let step = thinning_step(width, height, settings);
+    (step, width.div_ceil(step), height.div_ceil(step))
+}
+
+fn start_root_capture(settings: &CaptureSettings, stream_info: SharedStreamInfo) -> Result<FrameReceiver, CaptureError> {
+    let mut grabber = RootGrabber::open()?;
+    let settings = settings.clone();
+    let (mut width, mut height) = (grabber.width as u32, grabber.height as u32);
+    let (mut step, mut frame_width, mut frame_height) = frame_geometry(width, height, &settings);
+
+    let interval = settings.poll_interval();
+    let framerate = 1.0 / interval.as_secs_f64();
+    *stream_info.lock().unwrap() = Some(StreamInfo {
+        format: format_name(VideoFormat::BGRx),
+        width: frame_width,
+        height: frame_height,
+        framerate,
+        variable_framerate: false,
+        delivered_fps: 0.0,
+        output: None,
+    });
+
+    let (frame_sender, frame_receiver) = watch::channel(None);
+
+    thread::spawn(move || {
+        let started_at = Instant::now();
+        let mut sequence = 0u64;
+        loop {
+            match grabber.follow_resize() {
+                Ok(false) => {},
+                Ok(true) => {
+                    (width, height) = (grabber.width as u32, grabber.height as u32);
+                    (step, frame_width, frame_height) = frame_geometry(width, height, &settings);
+                    if let Some(info) = stream_info.lock().unwrap().as_mut() {
+                        info.width = frame_width;
+                        info.height = frame_height;
+                    }
+                    println!("Screen resized to {}x{}", width, height);
+                },
+                Err(e) => {
+                    eprintln!("X11 capture error: {}", e);
+                    return;
+                }
+            }
+
+            let grabbed_at = Instant::now();
+            let data = match grabber.grab() {
+                Ok(image) => copy_thinned(image, width, height, width as usize * 4, step, false),
+                // A resize that lands after the check fails the grab with
+                // BadMatch; a later iteration picks the new size up once its
+                // ConfigureNotify arrives, so don't spin until then
+                Err(_) if grabber.size_changed() => {
+                    thread::sleep(interval);
+                    continue;
+                },
+                Err(e) => {
+                    eprintln!("X11 capture error: {}", e);
+                    return;
+                }
+            };
+
+            sequence += 1;
+            let frame = FrameData {
+                data,
+                width: frame_width,
+                height: frame_height,
+                format: VideoFormat::BGRx,
+                timestamp: grabbed_at,
+                sequence,
+            };
+
+            // Monitoring was stopped
+            if frame_sender.send(Some(Arc::new(frame))).is_err() {
+                return;
+            }
+
+            if let Some(info) = stream_info.lock().unwrap().as_mut() {
+                info.delivered_fps = sequence as f64 / started_at.elapsed().as_secs_f64();
+            }
+
+            thread::sleep(interval.saturating_sub(grabbed_at.elapsed()));
+        }
+    });
+
+    Ok(frame_receiver)
+}
+
+impl FrameSource for X11ShmSource {
+    fn name(&self) -> &'static str {
+        "x11-shm"
+    }
+
+    fn start<'a>(&'a self, settings: &'a CaptureSettings) -> StartFuture<'a> {
+        *self.stream_info.lock().unwrap() = None;
+        let started = start_root_capture(settings, self.stream_info.clone());
+        Box::pin(async move { started })
+    }
+
+    fn stop(&self) {
+        *self.stream_info.lock().unwrap() = None;
+    }
+
+    fn metadata(&self) -> Option<StreamInfo> {
+        self.stream_info.lock().unwrap().clone()
+    }
+}